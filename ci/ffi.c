@@ -0,0 +1,41 @@
+/* Links against the staticlib built with `--features ffi` and decodes a few packets */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "itm.h"
+
+static char text[16];
+static size_t text_len = 0;
+
+static void on_packet(void *context, const itm_packet_t *packet) {
+    size_t *count = context;
+    *count += 1;
+
+    if (packet->kind == ITM_PACKET_INSTRUMENTATION) {
+        memcpy(text + text_len, packet->payload, packet->payload_len);
+        text_len += packet->payload_len;
+    }
+}
+
+int main(void) {
+    /* Instrumentation "Hi"; Overflow; Instrumentation "!" */
+    const uint8_t bytes[] = {0x02, 0x48, 0x69, 0x70, 0x01, 0x21};
+    size_t count = 0;
+
+    itm_decoder_t *decoder = itm_decoder_new(on_packet, &count);
+    if (decoder == NULL || itm_decoder_new(NULL, NULL) != NULL) {
+        return 1;
+    }
+
+    itm_decoder_feed(decoder, bytes, 2);
+    itm_decoder_feed(decoder, bytes + 2, sizeof(bytes) - 2);
+    itm_decoder_free(decoder);
+
+    if (count != 3 || text_len != 3 || memcmp(text, "Hi!", 3) != 0) {
+        fprintf(stderr, "unexpected output: %zu packets, \"%.*s\"\n", count, (int)text_len, text);
+        return 1;
+    }
+
+    return 0;
+}
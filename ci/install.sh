@@ -1,7 +1,8 @@
 set -euxo pipefail
 
 main() {
-    true
+    # no_std target
+    rustup target add thumbv7m-none-eabi
 }
 
 main
@@ -2,9 +2,22 @@ set -euxo pipefail
 
 main() {
     cargo check --target $TARGET
+    cargo check --target $TARGET --no-default-features
+    cargo check --target thumbv7m-none-eabi --no-default-features --features serde
 
     cargo test --target $TARGET
+    cargo test --target $TARGET --features serde
+    cargo test --target $TARGET --features ffi
+    cargo test --target $TARGET --features "serde tokio"
     cargo test --target $TARGET --release
+
+    cargo doc --target $TARGET --no-deps --all-features
+
+    # C API: build a static library and link a C program against it
+    cargo rustc --target $TARGET --lib --features ffi --crate-type staticlib
+    cc -Wall -Werror -I include ci/ffi.c target/$TARGET/debug/libitm.a -lpthread -ldl -lm \
+       -o target/ffi
+    target/ffi
 }
 
 main
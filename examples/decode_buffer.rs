@@ -0,0 +1,31 @@
+//! Decodes an in-memory ITM byte stream and prints the packets
+//!
+//! ``` text
+//! $ cargo run --example decode_buffer
+//! ```
+
+use std::io::Cursor;
+
+use itm::Stream;
+
+fn main() {
+    let bytes = vec![
+        // Synchronization
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, //
+        // Instrumentation; port 0; "Hi"
+        0x02, 0x48, 0x69, //
+        // Local timestamp
+        0xc0, 0x81, 0x01, //
+        // Overflow
+        0x70, //
+        // Instrumentation; port 1; 0xdeadbeef
+        0x0b, 0xef, 0xbe, 0xad, 0xde,
+    ];
+
+    for packet in Stream::new(Cursor::new(bytes), false) {
+        match packet.expect("I/O error") {
+            Ok(packet) => println!("{:?}", packet),
+            Err(e) => println!("error: {}", e),
+        }
+    }
+}
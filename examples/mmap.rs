@@ -0,0 +1,41 @@
+//! Decodes a capture file by memory-mapping it, without copying its contents
+//!
+//! ``` text
+//! $ cargo run --example mmap -- trace.bin
+//! ```
+
+use std::{env, fs::File, io};
+
+use itm::decode;
+use memmap2::Mmap;
+
+fn main() -> io::Result<()> {
+    let path = env::args()
+        .nth(1)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "usage: mmap <file>"))?;
+
+    let file = File::open(path)?;
+    // NOTE the file must not be modified while it's mapped
+    let map = unsafe { Mmap::map(&file)? };
+
+    let mut bytes = &map[..];
+    while !bytes.is_empty() {
+        match decode(bytes) {
+            Some((res, len)) => {
+                match res {
+                    Ok(packet) => println!("{:?}", packet),
+                    Err(e) => eprintln!("{}", e),
+                }
+
+                bytes = &bytes[len..];
+            }
+            None => {
+                // the capture ends in the middle of a packet
+                eprintln!("truncated packet of length {}", bytes.len());
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,27 @@
+//! Decodes the ITM packets received over a TCP connection and prints them as JSON, one per line
+//!
+//! ``` text
+//! $ cargo run --example tcp_json --features "serde tokio" -- 127.0.0.1:3443
+//! ```
+
+use std::{env, io};
+
+use itm::AsyncDecoder;
+use tokio::net::TcpStream;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> io::Result<()> {
+    let addr = env::args()
+        .nth(1)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "usage: tcp_json <addr>"))?;
+
+    let mut decoder = AsyncDecoder::new(TcpStream::connect(addr).await?);
+    while let Some(res) = decoder.read_packet().await? {
+        match res {
+            Ok(packet) => println!("{}", serde_json::to_string(&packet)?),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // `decode` must make progress on any complete packet, valid or not
+    if let Some((_, len)) = itm::decode(data) {
+        assert!(len >= 1 && len <= data.len());
+    }
+
+    // every item consumes at least one byte so the stream must end after at most `data.len()`
+    // items
+    let mut stream = itm::Stream::new(data, false);
+    let mut items = 0;
+    while stream.next().unwrap().is_some() {
+        items += 1;
+        assert!(items <= data.len());
+    }
+});
@@ -0,0 +1,71 @@
+/* C API of the `itm` crate
+ *
+ * Build a static (or dynamic) library with the `ffi` feature and link against it:
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type staticlib
+ *     cc -I include app.c target/release/libitm.a -lpthread -ldl -lm
+ *
+ * Use `--crate-type cdylib` to get a shared library instead, e.g. for Python's `ctypes`. */
+
+#ifndef ITM_H
+#define ITM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* kinds of packet */
+#define ITM_PACKET_RESERVED_HEADER 0
+#define ITM_PACKET_MALFORMED 1
+#define ITM_PACKET_SYNCHRONIZATION 2
+#define ITM_PACKET_OVERFLOW 3
+#define ITM_PACKET_INSTRUMENTATION 4
+#define ITM_PACKET_LOCAL_TIMESTAMP 5
+#define ITM_PACKET_GTS1 6
+#define ITM_PACKET_GTS2 7
+#define ITM_PACKET_STIMULUS_PORT_PAGE 8
+#define ITM_PACKET_EXTENSION 9
+#define ITM_PACKET_EVENT_COUNTER 10
+#define ITM_PACKET_EXCEPTION_TRACE 11
+#define ITM_PACKET_PERIODIC_PC_SAMPLE 12
+#define ITM_PACKET_DATA_TRACE_PC_VALUE 13
+#define ITM_PACKET_DATA_TRACE_ADDRESS 14
+#define ITM_PACKET_DATA_TRACE_DATA_VALUE 15
+
+typedef struct itm_packet {
+    /* one of the ITM_PACKET_* constants */
+    uint32_t kind;
+    /* stimulus port of an instrumentation packet; zero otherwise */
+    uint8_t port;
+    /* number of valid bytes in `payload` */
+    uint8_t payload_len;
+    /* payload of an instrumentation packet */
+    uint8_t payload[4];
+    /* raw bytes of the packet, including the header; only valid during the callback */
+    const uint8_t *bytes;
+    size_t len;
+} itm_packet_t;
+
+typedef void (*itm_callback_t)(void *context, const itm_packet_t *packet);
+
+typedef struct itm_decoder itm_decoder_t;
+
+/* creates a decoder that passes the decoded packets, along with `context`, to `callback`; returns
+   NULL if `callback` is NULL */
+itm_decoder_t *itm_decoder_new(itm_callback_t callback, void *context);
+
+/* feeds `len` bytes to the decoder; `callback` is invoked for every completed packet. `callback`
+   must not call `itm_decoder_feed` or `itm_decoder_free` on the same decoder */
+void itm_decoder_feed(itm_decoder_t *decoder, const uint8_t *bytes, size_t len);
+
+/* frees the decoder; buffered bytes that don't form a complete packet are discarded */
+void itm_decoder_free(itm_decoder_t *decoder);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* ITM_H */
@@ -0,0 +1,200 @@
+//! C API
+//!
+//! This module exposes a push-based ITM decoder to other languages. See `include/itm.h` for the
+//! matching C declarations.
+//!
+//! The crate is built as a Rust library by default; to get a library that C code can link
+//! against pass the crate type to `cargo rustc`:
+//!
+//! ``` text
+//! $ cargo rustc --release --lib --features ffi --crate-type staticlib # or cdylib
+//! ```
+//!
+//! ``` c
+//! #include "itm.h"
+//!
+//! static void on_packet(void *context, const itm_packet_t *packet) {
+//!     if (packet->kind == ITM_PACKET_INSTRUMENTATION) {
+//!         fwrite(packet->payload, 1, packet->payload_len, stdout);
+//!     }
+//! }
+//!
+//! itm_decoder_t *decoder = itm_decoder_new(on_packet, NULL);
+//! itm_decoder_feed(decoder, bytes, len);
+//! itm_decoder_free(decoder);
+//! ```
+
+use core::ffi::c_void;
+
+use crate::{Decoder, Error, Packet};
+
+/// The header byte is reserved; the packet is a single byte long
+pub const ITM_PACKET_RESERVED_HEADER: u32 = 0;
+/// Malformed packet; e.g. a payload that doesn't match its header
+pub const ITM_PACKET_MALFORMED: u32 = 1;
+/// Synchronization packet
+pub const ITM_PACKET_SYNCHRONIZATION: u32 = 2;
+/// Overflow packet
+pub const ITM_PACKET_OVERFLOW: u32 = 3;
+/// Instrumentation packet
+pub const ITM_PACKET_INSTRUMENTATION: u32 = 4;
+/// Local timestamp packet
+pub const ITM_PACKET_LOCAL_TIMESTAMP: u32 = 5;
+/// Global timestamp packet (format 1)
+pub const ITM_PACKET_GTS1: u32 = 6;
+/// Global timestamp packet (format 2)
+pub const ITM_PACKET_GTS2: u32 = 7;
+/// Stimulus port page packet
+pub const ITM_PACKET_STIMULUS_PORT_PAGE: u32 = 8;
+/// Extension packet, other than a stimulus port page
+pub const ITM_PACKET_EXTENSION: u32 = 9;
+/// Event counter packet
+pub const ITM_PACKET_EVENT_COUNTER: u32 = 10;
+/// Exception trace packet
+pub const ITM_PACKET_EXCEPTION_TRACE: u32 = 11;
+/// Periodic PC sample packet
+pub const ITM_PACKET_PERIODIC_PC_SAMPLE: u32 = 12;
+/// Data trace PC value packet
+pub const ITM_PACKET_DATA_TRACE_PC_VALUE: u32 = 13;
+/// Data trace address packet
+pub const ITM_PACKET_DATA_TRACE_ADDRESS: u32 = 14;
+/// Data trace data value packet
+pub const ITM_PACKET_DATA_TRACE_DATA_VALUE: u32 = 15;
+
+/// A decoded packet, as passed to the callback
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct itm_packet_t {
+    /// One of the `ITM_PACKET_*` constants
+    pub kind: u32,
+    /// Stimulus port of an Instrumentation packet; zero for the other kinds of packet
+    pub port: u8,
+    /// Number of valid bytes in `payload`
+    pub payload_len: u8,
+    /// Payload of an Instrumentation packet
+    pub payload: [u8; 4],
+    /// The raw bytes of the packet, including the header
+    ///
+    /// This pointer is only valid for the duration of the callback
+    pub bytes: *const u8,
+    /// Number of bytes pointed to by `bytes`
+    pub len: usize,
+}
+
+/// Callback invoked for every decoded packet
+///
+/// The first argument is the `context` pointer passed to `itm_decoder_new`. `None` corresponds to a
+/// `NULL` function pointer on the C side.
+#[allow(non_camel_case_types)]
+pub type itm_callback_t = Option<extern "C" fn(*mut c_void, *const itm_packet_t)>;
+
+/// A push-based ITM decoder
+#[allow(non_camel_case_types)]
+pub struct itm_decoder_t {
+    callback: extern "C" fn(*mut c_void, *const itm_packet_t),
+    context: *mut c_void,
+    decoder: Decoder,
+}
+
+/// Creates a new decoder that will pass the decoded packets, along with `context`, to `callback`
+///
+/// The decoder must be freed with `itm_decoder_free`. Returns `NULL` if `callback` is `NULL`.
+#[no_mangle]
+pub extern "C" fn itm_decoder_new(
+    callback: itm_callback_t,
+    context: *mut c_void,
+) -> *mut itm_decoder_t {
+    let callback = match callback {
+        Some(callback) => callback,
+        None => return core::ptr::null_mut(),
+    };
+
+    Box::into_raw(Box::new(itm_decoder_t {
+        callback,
+        context,
+        decoder: Decoder::new(),
+    }))
+}
+
+/// Feeds `len` bytes to the decoder
+///
+/// The callback is invoked, before this function returns, for every packet completed by these
+/// bytes. Bytes that don't form a complete packet yet are kept until the next call.
+///
+/// # Safety
+///
+/// `decoder` must have been returned by `itm_decoder_new` and not freed yet. `bytes` must point to
+/// `len` readable bytes; it can be null if `len` is zero. The callback must not call
+/// `itm_decoder_feed` or `itm_decoder_free` on the same decoder.
+#[no_mangle]
+pub unsafe extern "C" fn itm_decoder_feed(
+    decoder: *mut itm_decoder_t,
+    bytes: *const u8,
+    len: usize,
+) {
+    let decoder = &mut *decoder;
+
+    if len != 0 {
+        decoder
+            .decoder
+            .feed(core::slice::from_raw_parts(bytes, len));
+    }
+
+    while let Some((res, bytes)) = decoder.decoder.next_packet_bytes() {
+        let packet = to_c(res, bytes);
+        (decoder.callback)(decoder.context, &packet);
+    }
+}
+
+/// Frees a decoder created by `itm_decoder_new`
+///
+/// Buffered bytes that don't form a complete packet are discarded
+///
+/// # Safety
+///
+/// `decoder` must have been returned by `itm_decoder_new` and not freed yet, or be null
+#[no_mangle]
+pub unsafe extern "C" fn itm_decoder_free(decoder: *mut itm_decoder_t) {
+    if !decoder.is_null() {
+        drop(Box::from_raw(decoder));
+    }
+}
+
+fn to_c(res: Result<Packet, Error>, bytes: &[u8]) -> itm_packet_t {
+    let mut port = 0;
+    let mut payload_len = 0;
+    let mut payload = [0; 4];
+
+    let kind = match res {
+        Err(Error::ReservedHeader { .. }) => ITM_PACKET_RESERVED_HEADER,
+        Err(Error::MalformedPacket { .. }) => ITM_PACKET_MALFORMED,
+        Ok(Packet::Synchronization(_)) => ITM_PACKET_SYNCHRONIZATION,
+        Ok(Packet::Overflow) => ITM_PACKET_OVERFLOW,
+        Ok(Packet::Instrumentation(i)) => {
+            port = i.port();
+            payload_len = i.payload().len() as u8;
+            payload[..i.payload().len()].copy_from_slice(i.payload());
+            ITM_PACKET_INSTRUMENTATION
+        }
+        Ok(Packet::LocalTimestamp(_)) => ITM_PACKET_LOCAL_TIMESTAMP,
+        Ok(Packet::GTS1(_)) => ITM_PACKET_GTS1,
+        Ok(Packet::GTS2(_)) => ITM_PACKET_GTS2,
+        Ok(Packet::StimulusPortPage(_)) => ITM_PACKET_STIMULUS_PORT_PAGE,
+        Ok(Packet::Extension(_)) => ITM_PACKET_EXTENSION,
+        Ok(Packet::EventCounter(_)) => ITM_PACKET_EVENT_COUNTER,
+        Ok(Packet::ExceptionTrace(_)) => ITM_PACKET_EXCEPTION_TRACE,
+        Ok(Packet::PeriodicPcSample(_)) => ITM_PACKET_PERIODIC_PC_SAMPLE,
+        Ok(Packet::DataTracePcValue(_)) => ITM_PACKET_DATA_TRACE_PC_VALUE,
+        Ok(Packet::DataTraceAddress(_)) => ITM_PACKET_DATA_TRACE_ADDRESS,
+        Ok(Packet::DataTraceDataValue(_)) => ITM_PACKET_DATA_TRACE_DATA_VALUE,
+    };
+
+    itm_packet_t {
+        kind,
+        port,
+        payload_len,
+        payload,
+        bytes: bytes.as_ptr(),
+        len: bytes.len(),
+    }
+}
@@ -3,36 +3,64 @@
 //! # References
 //!
 //! - [ARMv7-M Architecture Reference Manual (DDI 0403E.b)][0] - Appendix D4 Debug ITM and DWT
-//! Packet Protocol
+//!   Packet Protocol
 //!
 //! [0]: https://static.docs.arm.com/ddi0403/eb/DDI0403E_B_armv7m_arm.pdf
 //!
 //! - [CoreSight Components Technical Reference Manual (DDI 0314H)][1] - Chapter 12 Instrumentation
-//! Trace Macrocell
+//!   Trace Macrocell
 //!
 //! [1]: http://infocenter.arm.com/help/topic/com.arm.doc.ddi0314h/DDI0314H_coresight_components_trm.pdf
-
+//!
+//! # Cargo features
+//!
+//! - `std` (enabled by default): the `Stream` API, which decodes packets out of a
+//!   `std::io::Read`-able source, and the `std::error::Error` implementation of `Error`. Disable
+//!   this feature to use the crate in `no_std` contexts; packets can still be decoded from byte
+//!   slices using the `decode` function.
+//! - `ffi`: a C API, declared in `include/itm.h`, to decode packets from other languages. Implies
+//!   `std`.
+//! - `tokio`: `AsyncDecoder`, which decodes packets out of a `tokio::io::AsyncRead`-able source.
+//!   Implies `std`.
+//! - `serde`: implements `serde`'s `Serialize` and `Deserialize` traits for `Packet` and the
+//!   packet types. Packets are (de)serialized as the values that their accessors return, and
+//!   payloads as hex strings; deserialization rejects values that no ITM packet can encode.
+
+#![cfg_attr(not(feature = "std"), no_std)]
 #![deny(missing_docs)]
 #![deny(warnings)]
 
 use core::fmt;
+#[cfg(feature = "std")]
+use core::mem;
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::error;
+#[cfg(feature = "std")]
 use std::io::{self, ErrorKind, Read};
 
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt};
+
 use byteorder::{ByteOrder, LE};
 use either::Either;
-use failure_derive::Fail;
 
 use crate::packet::{
-    DataTraceAddress, DataTraceDataValue, DataTracePcValue, EventCounter, ExceptionTrace, Function,
-    Instrumentation, LocalTimestamp, PeriodicPcSample, StimulusPortPage, Synchronization, GTS1,
-    GTS2,
+    DataTraceAddress, DataTraceDataValue, DataTracePcValue, EventCounter, ExceptionTrace,
+    Extension, Function, Instrumentation, LocalTimestamp, PeriodicPcSample, StimulusPortPage,
+    Synchronization, GTS1, GTS2,
 };
 
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod packet;
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests;
+pub mod tpiu;
 
 /// A stream of ITM packets
+#[cfg(feature = "std")]
 pub struct Stream<R>
 where
     R: Read,
@@ -46,9 +74,16 @@ where
     keep_reading: bool,
     // number of read bytes in `buffer`
     len: usize,
+    // the stimulus port page selected by the last `StimulusPortPage` packet
+    page: u8,
     reader: R,
+    // NOTE `tracker.consumed` is the offset of the first byte in `buffer`
+    tracker: Tracker,
+    // number of zeros of a (very long) synchronization packet shifted out of `buffer`
+    zeros: usize,
 }
 
+#[cfg(feature = "std")]
 impl<R> fmt::Debug for Stream<R>
 where
     R: fmt::Debug + Read,
@@ -58,11 +93,15 @@ where
             .field("at_eof", &self.at_eof)
             .field("buffer", &&self.buffer[..self.len])
             .field("keep_reading", &self.keep_reading)
+            .field("page", &self.page)
             .field("reader", &self.reader)
+            .field("tracker", &self.tracker)
+            .field("zeros", &self.zeros)
             .finish()
     }
 }
 
+#[cfg(feature = "std")]
 impl<R> Stream<R>
 where
     R: Read,
@@ -77,7 +116,10 @@ where
             at_eof: false,
             keep_reading,
             len: 0,
+            page: 0,
             reader,
+            tracker: Tracker::default(),
+            zeros: 0,
         }
     }
 
@@ -89,26 +131,46 @@ where
     /// set to `false` (see constructor)
     ///
     /// `Ok(Some(..))` is the result of parsing the stream data into an ITM packet
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> io::Result<Option<Result<Packet, Error>>> {
         if self.at_eof {
             return Ok(None);
         }
 
         'extract: loop {
-            match parse(&self.buffer[..self.len]) {
-                Ok(packet) => {
-                    self.rotate_left(usize::from(packet.len()));
+            match decode(&self.buffer[..self.len]) {
+                Some((mut res, len)) => {
+                    // the packet starts with the zeros that have been shifted out, if any
+                    let zeros = mem::replace(&mut self.zeros, 0);
+                    if zeros != 0 {
+                        let total = saturate(zeros + len);
+                        match res {
+                            Ok(Packet::Synchronization(ref mut s)) => s.len = total,
+                            Err(Error::MalformedPacket { ref mut len, .. }) => *len = total,
+                            _ => unreachable!(),
+                        }
+                    }
 
-                    return Ok(Some(Ok(packet)));
-                }
-                // parsing error
-                Err(Either::Left(e)) => {
-                    // skip malformed packet
-                    self.rotate_left(usize::from(e.len()));
+                    select_page(&mut self.page, &mut res);
+                    self.tracker.record(&res, zeros + len);
 
-                    return Ok(Some(Err(e)));
+                    // NOTE in case of a parsing error this skips the malformed packet
+                    self.rotate_left(len);
+
+                    return Ok(Some(res));
+                }
+                None if self.len == self.buffer.len() => {
+                    // only a (very long) synchronization packet can be larger than the buffer, so
+                    // the buffer is full of zeros. Count them and shift them out, keeping enough of
+                    // them to recognize the end of the packet, to make room for more data. Without
+                    // this we would mistake the zero-length read below for EOF
+                    let shift = self.len - 5;
+                    self.zeros += shift;
+                    self.rotate_left(shift);
+
+                    continue 'extract;
                 }
-                Err(Either::Right(NeedMoreBytes)) => {
+                None => {
                     // need more bytes
                     'read: loop {
                         match self.reader.read(&mut self.buffer[self.len..]) {
@@ -122,10 +184,14 @@ where
                                     } else {
                                         // truncated packet
                                         self.at_eof = true;
-                                        return Ok(Some(Err(Error::MalformedPacket {
+                                        let len = self.len + mem::replace(&mut self.zeros, 0);
+                                        let res = Err(Error::MalformedPacket {
                                             header: self.buffer[0],
-                                            len: self.len as u8,
-                                        })));
+                                            len: saturate(len),
+                                        });
+                                        self.tracker.record(&res, len);
+                                        self.len = 0;
+                                        return Ok(Some(res));
                                     }
                                 }
                             }
@@ -145,6 +211,102 @@ where
         }
     }
 
+    /// Discards bytes until the start of the next synchronization packet
+    ///
+    /// Some probes output a preamble, like a textual banner, before the ITM data; call this before
+    /// `next` to skip it. The next packet returned by `next` will be the synchronization packet and
+    /// its `offset` will be the number of skipped bytes.
+    ///
+    /// `Ok(false)` means that EOF has been reached before a synchronization packet was found --
+    /// this is only returned when `keep_reading` is set to `false` (see constructor). All the bytes
+    /// are skipped in that case and `next` will return `Ok(None)`.
+    pub fn skip_to_synchronization(&mut self) -> io::Result<bool> {
+        loop {
+            // a synchronization packet is (at least) 47 zero bits followed by a one bit; i.e. five
+            // zero bytes followed by `0x80`
+            let mut zeros = 0;
+            for i in 0..self.len {
+                match self.buffer[i] {
+                    0 => zeros += 1,
+                    0x80 if zeros >= 5 => {
+                        self.skip(i - zeros);
+                        return Ok(true);
+                    }
+                    _ => zeros = 0,
+                }
+            }
+
+            // keep the trailing zeros as they may be the start of a synchronization packet
+            self.skip(self.len - zeros.min(5));
+
+            loop {
+                match self.reader.read(&mut self.buffer[self.len..]) {
+                    Ok(0) => {
+                        if self.keep_reading {
+                            continue;
+                        } else {
+                            // reached EOF; the trailing zeros aren't a synchronization packet
+                            // either
+                            self.skip(self.len);
+                            self.at_eof = true;
+                            return Ok(false);
+                        }
+                    }
+                    Ok(len) => {
+                        self.len += len;
+                        break;
+                    }
+                    Err(e) => match e.kind() {
+                        ErrorKind::Interrupted => continue,
+                        _ => return Err(e),
+                    },
+                }
+            }
+        }
+    }
+
+    /// Returns the position of the packet last returned by `next`
+    ///
+    /// This is the offset, in bytes from the start of the stream, of the packet's header. This
+    /// includes malformed packets, which makes it possible to locate them in the raw trace data.
+    pub fn offset(&self) -> u64 {
+        self.tracker.offset
+    }
+
+    /// Discards the buffered, not yet decoded, bytes of this stream
+    ///
+    /// Use this when the trace link is known to have restarted; the next packet will be decoded
+    /// from the next bytes read from the inner `Reader` object. This also clears the EOF condition
+    /// so the stream can be resumed after `next` returned `Ok(None)`, and selects stimulus port
+    /// page 0.
+    ///
+    /// The discarded bytes still count towards `offset` and `Stats::bytes`. The statistics are not
+    /// cleared.
+    pub fn reset(&mut self) {
+        self.at_eof = false;
+        self.tracker.discard(self.len + self.zeros);
+        self.len = 0;
+        self.page = 0;
+        self.zeros = 0;
+    }
+
+    /// Returns statistics about the packets returned by `next` so far
+    pub fn stats(&self) -> &Stats {
+        &self.tracker.stats
+    }
+
+    // discards the first `len` bytes of the buffer
+    fn skip(&mut self, len: usize) {
+        if len != 0 {
+            // the zeros shifted out of the buffer precede the skipped bytes
+            let zeros = mem::replace(&mut self.zeros, 0);
+            self.tracker.discard(zeros);
+        }
+
+        self.tracker.discard(len);
+        self.rotate_left(len);
+    }
+
     // like `slice.rotate_left` but doesn't touch the unused parts of the buffer
     fn rotate_left(&mut self, shift: usize) {
         for i in 0..self.len - shift {
@@ -155,26 +317,403 @@ where
     }
 }
 
+/// Iterates over the packets in the stream
+///
+/// Each item has the same meaning as the `Ok(Some(..))` / `Err(..)` return values of
+/// `Stream::next`; the iterator ends when `Stream::next` returns `Ok(None)`
+#[cfg(feature = "std")]
+impl<R> Iterator for Stream<R>
+where
+    R: Read,
+{
+    type Item = io::Result<Result<Packet, Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match Stream::next(self) {
+            Ok(Some(packet)) => Some(Ok(packet)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// A push-based ITM decoder
+///
+/// Unlike `Stream`, this doesn't read from a `Reader` object; bytes are fed to the decoder as they
+/// become available, in chunks of any size, and packets are pulled from it as they are completed.
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    // the stimulus port page selected by the last `StimulusPortPage` packet
+    page: u8,
+    // start of the bytes in `buffer` that have not been decoded yet
+    pos: usize,
+    tracker: Tracker,
+}
+
+#[cfg(feature = "std")]
+impl Decoder {
+    /// Creates a new decoder
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Appends `bytes` to the bytes to decode
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.drain(..self.pos);
+        self.pos = 0;
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Discards the bytes that have been fed to the decoder but not decoded yet
+    ///
+    /// Use this when the trace link is known to have restarted; the next packet will be decoded
+    /// from the next bytes fed to the decoder. This also selects stimulus port page 0.
+    ///
+    /// The discarded bytes still count towards `offset` and `Stats::bytes`. The statistics are not
+    /// cleared.
+    pub fn reset(&mut self) {
+        self.tracker.discard(self.buffer.len() - self.pos);
+        self.buffer.clear();
+        self.pos = 0;
+        self.page = 0;
+    }
+
+    /// Returns the next packet
+    ///
+    /// `None` means that the bytes fed so far don't contain a complete packet and that more bytes
+    /// are needed
+    pub fn next_packet(&mut self) -> Option<Result<Packet, Error>> {
+        self.next_packet_bytes().map(|(res, _)| res)
+    }
+
+    /// Returns the position of the packet last returned by `next_packet`
+    ///
+    /// This is the offset, in bytes from the first byte fed to the decoder, of the packet's header.
+    pub fn offset(&self) -> u64 {
+        self.tracker.offset
+    }
+
+    /// Returns statistics about the packets returned by `next_packet` so far
+    pub fn stats(&self) -> &Stats {
+        &self.tracker.stats
+    }
+
+    // like `next_packet` but also returns the bytes that the packet spans
+    pub(crate) fn next_packet_bytes(&mut self) -> Option<(Result<Packet, Error>, &[u8])> {
+        let start = self.pos;
+        let (mut res, len) = decode(&self.buffer[start..])?;
+        select_page(&mut self.page, &mut res);
+        self.tracker.record(&res, len);
+        self.pos += len;
+
+        Some((res, &self.buffer[start..self.pos]))
+    }
+
+    // reports the bytes that don't form a complete packet as a malformed packet, and discards them
+    #[cfg(feature = "tokio")]
+    fn flush(&mut self) -> Option<Error> {
+        let len = self.buffer.len() - self.pos;
+        if len == 0 {
+            return None;
+        }
+
+        let res = Err(Error::MalformedPacket {
+            header: self.buffer[self.pos],
+            len: len as u8,
+        });
+        self.tracker.record(&res, len);
+        self.buffer.clear();
+        self.pos = 0;
+
+        res.err()
+    }
+}
+
+/// An asynchronous stream of ITM packets
+///
+/// This is the `tokio` counterpart of `Stream`; it decodes packets using a `Decoder`.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct AsyncDecoder<R>
+where
+    R: AsyncRead + Unpin,
+{
+    decoder: Decoder,
+    reader: R,
+}
+
+#[cfg(feature = "tokio")]
+impl<R> AsyncDecoder<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Creates a stream of ITM packets from the given `AsyncRead` object
+    pub fn new(reader: R) -> AsyncDecoder<R> {
+        AsyncDecoder {
+            decoder: Decoder::new(),
+            reader,
+        }
+    }
+
+    /// Returns the next packet in this stream
+    ///
+    /// The return value has the same meaning as the one of `Stream::next`. A packet truncated by
+    /// the EOF of the inner `AsyncRead` object is reported as a malformed packet.
+    pub async fn read_packet(&mut self) -> io::Result<Option<Result<Packet, Error>>> {
+        let mut buffer = [0; 64];
+
+        loop {
+            if let Some(res) = self.decoder.next_packet() {
+                return Ok(Some(res));
+            }
+
+            let len = self.reader.read(&mut buffer).await?;
+            if len == 0 {
+                // reached EOF
+                return Ok(self.decoder.flush().map(Err));
+            }
+
+            self.decoder.feed(&buffer[..len]);
+        }
+    }
+
+    /// Returns the position of the packet last returned by `read_packet`
+    ///
+    /// See `Stream::offset`
+    pub fn offset(&self) -> u64 {
+        self.decoder.offset()
+    }
+
+    /// Returns statistics about the packets returned by `read_packet` so far
+    pub fn stats(&self) -> &Stats {
+        self.decoder.stats()
+    }
+}
+
+// Tracks the position of the packets extracted from a byte stream, and statistics about them
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default)]
+struct Tracker {
+    // number of bytes consumed so far
+    consumed: u64,
+    // offset of the last extracted packet
+    offset: u64,
+    stats: Stats,
+}
+
+#[cfg(feature = "std")]
+impl Tracker {
+    // updates the position and statistics after extracting a packet of `len` bytes
+    fn record(&mut self, res: &Result<Packet, Error>, len: usize) {
+        self.offset = self.consumed;
+        self.consumed += len as u64;
+
+        self.stats.bytes += len as u64;
+        match *res {
+            Ok(packet) => {
+                self.stats.packets += 1;
+                *self.stats.kinds.entry(packet.kind()).or_default() += 1;
+
+                if let Packet::Instrumentation(i) = packet {
+                    let port = self.stats.ports.entry(i.port()).or_default();
+                    port.packets += 1;
+                    port.bytes += i.payload().len() as u64;
+                }
+            }
+            Err(_) => self.stats.errors += 1,
+        }
+    }
+
+    // accounts for `len` bytes that are dropped without being decoded
+    fn discard(&mut self, len: usize) {
+        self.consumed += len as u64;
+        self.stats.bytes += len as u64;
+    }
+}
+
+/// Statistics about the packets extracted from a `Stream`, `Decoder` or `AsyncDecoder`
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    bytes: u64,
+    errors: u64,
+    kinds: BTreeMap<PacketKind, u64>,
+    packets: u64,
+    ports: BTreeMap<u8, PortStats>,
+}
+
+#[cfg(feature = "std")]
+impl Stats {
+    /// Number of bytes extracted from the stream, including the bytes of malformed packets and the
+    /// bytes discarded by `Stream::skip_to_synchronization` and the `reset` methods
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Number of decoding errors; i.e. reserved headers and malformed packets
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    /// Number of successfully decoded packets of the given `kind`
+    pub fn kind(&self, kind: PacketKind) -> u64 {
+        self.kinds.get(&kind).cloned().unwrap_or(0)
+    }
+
+    /// Number of overflow packets
+    pub fn overflows(&self) -> u64 {
+        self.kind(PacketKind::Overflow)
+    }
+
+    /// Number of successfully decoded packets
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    /// Statistics about the instrumentation packets sent to the given stimulus `port`
+    pub fn port(&self, port: u8) -> PortStats {
+        self.ports.get(&port).cloned().unwrap_or_default()
+    }
+
+    /// Statistics about the instrumentation packets of all the stimulus ports that have been
+    /// active, in ascending port order
+    pub fn ports<'a>(&'a self) -> impl Iterator<Item = (u8, PortStats)> + 'a {
+        self.ports.iter().map(|(&port, &stats)| (port, stats))
+    }
+
+    /// Number of synchronization packets
+    pub fn synchronizations(&self) -> u64 {
+        self.kind(PacketKind::Synchronization)
+    }
+}
+
+/// Statistics about the instrumentation packets sent to a stimulus port
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PortStats {
+    bytes: u64,
+    packets: u64,
+}
+
+#[cfg(feature = "std")]
+impl PortStats {
+    /// Number of payload bytes sent to the port
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Number of instrumentation packets sent to the port
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+}
+
+/// Reads the data sent to a single stimulus port
+///
+/// The bytes returned by `Read::read` are the concatenated payloads of the instrumentation packets
+/// sent to the selected stimulus port. All other packets, as well as malformed packets, are
+/// discarded.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct StimulusReader<R>
+where
+    R: Read,
+{
+    // payload of the last instrumentation packet
+    buffer: [u8; 4],
+    // number of bytes in `buffer`
+    len: usize,
+    port: u8,
+    // start of the bytes in `buffer` that have not been returned yet
+    pos: usize,
+    stream: Stream<R>,
+}
+
+#[cfg(feature = "std")]
+impl<R> StimulusReader<R>
+where
+    R: Read,
+{
+    /// Creates a reader of the data sent to stimulus `port` from the given stream of ITM packets
+    ///
+    /// Whether the reader returns EOF depends on the `keep_reading` setting of the `stream`
+    pub fn new(stream: Stream<R>, port: u8) -> StimulusReader<R> {
+        StimulusReader {
+            buffer: [0; 4],
+            len: 0,
+            port,
+            pos: 0,
+            stream,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R> Read for StimulusReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.len {
+            match self.stream.next()? {
+                None => return Ok(0),
+                Some(Ok(Packet::Instrumentation(i))) if i.port() == self.port => {
+                    let payload = i.payload();
+
+                    self.buffer[..payload.len()].copy_from_slice(payload);
+                    self.len = payload.len();
+                    self.pos = 0;
+                }
+                // not data for us
+                Some(_) => {}
+            }
+        }
+
+        let len = buf.len().min(self.len - self.pos);
+        buf[..len].copy_from_slice(&self.buffer[self.pos..self.pos + len]);
+        self.pos += len;
+
+        Ok(len)
+    }
+}
+
 /// ITM packet decoding errors
-#[derive(Debug, Fail)]
+#[derive(Debug)]
 pub enum Error {
     /// The packet starts with a reserved header byte
-    #[fail(display = "reserved header byte: {}", byte)]
     ReservedHeader {
         /// The header byte
         byte: u8,
     },
 
     /// The packet doesn't adhere to the (ARMv7-M) specification
-    #[fail(display = "malformed packet of length {} with header: {}", len, header)]
     MalformedPacket {
         /// The header of the malformed packet
         header: u8,
-        /// Length of the malformed packet in bytes, including the header
+        /// Length of the malformed packet in bytes, including the header; this saturates at
+        /// `u8::MAX`
         len: u8,
     },
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ReservedHeader { byte } => write!(f, "reserved header byte: {}", byte),
+            Error::MalformedPacket { header, len } => write!(
+                f,
+                "malformed packet of length {} with header: {}",
+                len, header
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for Error {}
+
 impl Error {
     fn len(&self) -> u8 {
         match *self {
@@ -185,7 +724,8 @@ impl Error {
 }
 
 /// An ITM packet
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Packet {
     /// Overflow packet
     Overflow,
@@ -201,6 +741,8 @@ pub enum Packet {
     GTS2(GTS2),
     /// Stimulus Port Page (Extension packet)
     StimulusPortPage(StimulusPortPage),
+    /// Extension packet, other than a Stimulus Port Page
+    Extension(Extension),
     /// Event Counter
     EventCounter(EventCounter),
     /// Exception Trace
@@ -216,6 +758,26 @@ pub enum Packet {
 }
 
 impl Packet {
+    /// The kind of this packet
+    pub fn kind(&self) -> PacketKind {
+        match *self {
+            Packet::Overflow => PacketKind::Overflow,
+            Packet::Synchronization(_) => PacketKind::Synchronization,
+            Packet::Instrumentation(_) => PacketKind::Instrumentation,
+            Packet::LocalTimestamp(_) => PacketKind::LocalTimestamp,
+            Packet::GTS1(_) => PacketKind::GTS1,
+            Packet::GTS2(_) => PacketKind::GTS2,
+            Packet::StimulusPortPage(_) => PacketKind::StimulusPortPage,
+            Packet::Extension(_) => PacketKind::Extension,
+            Packet::EventCounter(_) => PacketKind::EventCounter,
+            Packet::ExceptionTrace(_) => PacketKind::ExceptionTrace,
+            Packet::PeriodicPcSample(_) => PacketKind::PeriodicPcSample,
+            Packet::DataTracePcValue(_) => PacketKind::DataTracePcValue,
+            Packet::DataTraceAddress(_) => PacketKind::DataTraceAddress,
+            Packet::DataTraceDataValue(_) => PacketKind::DataTraceDataValue,
+        }
+    }
+
     /// The length of this packet in bytes, including the header
     fn len(&self) -> u8 {
         match *self {
@@ -232,6 +794,7 @@ impl Packet {
                 }
             }
             Packet::StimulusPortPage(_) => 1,
+            Packet::Extension(e) => e.len,
             Packet::EventCounter(_) => 2,
             Packet::ExceptionTrace(_) => 3,
             Packet::PeriodicPcSample(pps) => {
@@ -248,16 +811,103 @@ impl Packet {
     }
 }
 
+/// The kind of an ITM packet; i.e. a `Packet` without its data
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PacketKind {
+    /// Overflow packet
+    Overflow,
+    /// Synchronization packet
+    Synchronization,
+    /// Instrumentation packet
+    Instrumentation,
+    /// Local timestamp packet
+    LocalTimestamp,
+    /// Global timestamp packet (format 1)
+    GTS1,
+    /// Global timestamp packet (format 2)
+    GTS2,
+    /// Stimulus Port Page (Extension packet)
+    StimulusPortPage,
+    /// Extension packet, other than a Stimulus Port Page
+    Extension,
+    /// Event Counter
+    EventCounter,
+    /// Exception Trace
+    ExceptionTrace,
+    /// Periodic PC Sample
+    PeriodicPcSample,
+    /// Data Trace PC Value
+    DataTracePcValue,
+    /// Data Trace Address
+    DataTraceAddress,
+    /// Data Trace Data Value
+    DataTraceDataValue,
+}
+
+// Tracks the stimulus port page selected by `StimulusPortPage` packets and applies it to the port
+// of `Instrumentation` packets
+#[cfg(feature = "std")]
+fn select_page(page: &mut u8, res: &mut Result<Packet, Error>) {
+    match *res {
+        Ok(Packet::StimulusPortPage(spp)) => *page = spp.page,
+        Ok(Packet::Instrumentation(ref mut i)) => i.port += *page * 32,
+        _ => {}
+    }
+}
+
+/// Decodes an ITM packet from the start of the given buffer
+///
+/// `None` means that `input` doesn't contain a complete packet and that more bytes are needed
+///
+/// `Some((res, len))` is the result of parsing the start of `input` into an ITM packet, plus the
+/// number of bytes, including the header, that the packet spans. In the case of a parsing error
+/// `len` is the number of bytes to skip to get to the next packet.
+pub fn decode(input: &[u8]) -> Option<(Result<Packet, Error>, usize)> {
+    let res = match parse(input) {
+        Ok(packet) => Ok(packet),
+        Err(Either::Left(e)) => Err(e),
+        Err(Either::Right(NeedMoreBytes)) => return None,
+    };
+
+    let len = if input[0] == 0 {
+        // the length of a (very long) synchronization packet saturates at `u8::MAX` so count its
+        // zeros instead. `parse` has seen the non-zero byte that ends them
+        let zeros = input
+            .iter()
+            .position(|&byte| byte != 0)
+            .unwrap_or(input.len());
+        if res.is_ok() {
+            zeros + 1
+        } else {
+            zeros
+        }
+    } else {
+        match res {
+            Ok(packet) => usize::from(packet.len()),
+            Err(ref e) => usize::from(e.len()),
+        }
+    };
+
+    Some((res, len))
+}
+
+// Converts the length of a packet into the `u8` used by `Synchronization` and
+// `Error::MalformedPacket`, saturating at `u8::MAX`
+fn saturate(len: usize) -> u8 {
+    len.min(usize::from(u8::MAX)) as u8
+}
+
 /// Tries to parse an ITM packet from the start of the given buffer
 fn parse(input: &[u8]) -> Result<Packet, Either<Error, NeedMoreBytes>> {
     let header = input.first().cloned().ok_or(Either::Right(NeedMoreBytes))?;
 
     match Header::parse(header).map_err(Either::Left)? {
         Header::Synchronization => {
-            let mut cursor = 1u8;
+            let mut cursor = 1;
 
             loop {
-                match input.get(usize::from(cursor)) {
+                match input.get(cursor) {
                     Some(&0b0000_0000) => {
                         // still within the synchronization packet
                         cursor += 1;
@@ -267,13 +917,15 @@ fn parse(input: &[u8]) -> Result<Packet, Either<Error, NeedMoreBytes>> {
                         //  "Synchronization packet is at least forty-seven 0 bits followed by single 1
                         //  bit"
                         // valid synchronization packet
-                        break Ok(Packet::Synchronization(Synchronization { len: cursor + 1 }));
+                        break Ok(Packet::Synchronization(Synchronization {
+                            len: saturate(cursor + 1),
+                        }));
                     }
                     Some(_) => {
                         // malformed packet
                         break Err(Either::Left(Error::MalformedPacket {
                             header,
-                            len: cursor,
+                            len: saturate(cursor),
                         }));
                     }
                     None => {
@@ -306,45 +958,21 @@ fn parse(input: &[u8]) -> Result<Packet, Either<Error, NeedMoreBytes>> {
         }
 
         Header::LTS1 { tc } => {
-            // parse the payload
-            let mut delta = 0;
-            let mut cursor = 1u8;
-            loop {
-                let payload = input
-                    .get(usize::from(cursor))
-                    .cloned()
-                    .ok_or(Either::Right(NeedMoreBytes))?;
-
-                delta += (u32::from(payload) & 0b0111_1111) << (7 * (cursor - 1));
-
-                if payload & 0b1000_0000 == 0 {
-                    // the C (Continue) bit is zero; end of the packet
-                    break;
-                } else {
-                    // the C (Continue) bit is set
+            let (delta, len, continued) = read_continued(&input[1..], 4).map_err(Either::Right)?;
 
-                    if cursor == 4 {
-                        // payloads are at most 4 bytes in size
-                        // according to the spec the last C bit should be zero so this is an error
+            if continued {
+                // payloads are at most 4 bytes in size
+                // according to the spec the last C bit should be zero so this is an error
 
-                        // the final payload byte may have been lost and this could be a new
-                        // header byte so we consider the malformed packet to end at the third
-                        // payload byte
-                        return Err(Either::Left(Error::MalformedPacket {
-                            header,
-                            len: cursor,
-                        }));
-                    } else {
-                        cursor += 1;
-                        continue;
-                    }
-                }
+                // the final payload byte may have been lost and this could be a new header byte
+                // so we consider the malformed packet to end at the third payload byte
+                return Err(Either::Left(Error::MalformedPacket { header, len }));
             }
 
             Ok(Packet::LocalTimestamp(LocalTimestamp {
-                delta,
+                delta: delta as u32,
                 tc,
-                len: cursor + 1,
+                len: len + 1,
             }))
         }
 
@@ -355,104 +983,33 @@ fn parse(input: &[u8]) -> Result<Packet, Either<Error, NeedMoreBytes>> {
         })),
 
         Header::GTS1 => {
-            // parse the payload -- this is similar to parsing LTS1 payload
-            let mut bits = 0;
-            let mut clk_ch = false;
-            let mut wrap = false;
-            let mut cursor = 1u8;
-            loop {
-                let payload = input
-                    .get(usize::from(cursor))
-                    .cloned()
-                    .ok_or(Either::Right(NeedMoreBytes))?;
-
-                let mask = if cursor == 4 {
-                    0b0001_1111
-                } else {
-                    0b0111_1111
-                };
-                bits += (u32::from(payload) & mask) << (7 * (cursor - 1));
-
-                if payload & 0b1000_0000 == 0 {
-                    // the C (Continue) bit is zero; end of the packet
-                    if cursor == 4 {
-                        // the fourth payload byte has extra info
-                        clk_ch = payload & (1 << 5) != 0;
-                        wrap = payload & (1 << 6) != 0;
-                    }
+            let (payload, len, continued) =
+                read_continued(&input[1..], 4).map_err(Either::Right)?;
 
-                    break;
-                } else {
-                    // the C (Continue) bit is set
-
-                    if cursor == 4 {
-                        // payloads are at most 4 bytes in size
-                        // according to the spec the last C bit should be zero so this is an error
-
-                        // the final payload byte may have been lost and this could be a new
-                        // header byte so we consider the malformed packet to end at the third
-                        // payload byte
-                        return Err(Either::Left(Error::MalformedPacket {
-                            header,
-                            len: cursor,
-                        }));
-                    } else {
-                        cursor += 1;
-                        continue;
-                    }
-                }
+            if continued {
+                // like with LTS1 we consider the malformed packet to end at the third payload byte
+                return Err(Either::Left(Error::MalformedPacket { header, len }));
             }
 
+            // the fourth payload byte has extra info
             Ok(Packet::GTS1(GTS1 {
-                bits,
-                clk_ch,
-                len: cursor + 1,
-                wrap,
+                bits: (payload & 0x03ff_ffff) as u32,
+                clk_ch: payload & (1 << 26) != 0,
+                len: len + 1,
+                wrap: payload & (1 << 27) != 0,
             }))
         }
 
         Header::GTS2 => {
-            // parse the payload -- this is similar to parsing LTS1 payload
-            let mut bits = 0;
-            let mut cursor = 1u8;
-            let b64 = loop {
-                let payload = input
-                    .get(usize::from(cursor))
-                    .cloned()
-                    .ok_or(Either::Right(NeedMoreBytes))?;
-
-                bits += (u64::from(payload) & 0b0111_1111) << (7 * (cursor - 1));
-
-                if payload & 0b1000_0000 == 0 {
-                    // Continue (C) bit is zero
-                    if cursor == 4 {
-                        if payload >> 1 != 0 {
-                            return Err(Either::Left(Error::MalformedPacket {
-                                header,
-                                len: cursor,
-                            }));
-                        } else {
-                            break false;
-                        }
-                    } else if cursor == 6 {
-                        if payload >> 3 != 0 {
-                            return Err(Either::Left(Error::MalformedPacket {
-                                header,
-                                len: cursor,
-                            }));
-                        } else {
-                            break true;
-                        }
-                    } else {
-                        return Err(Either::Left(Error::MalformedPacket {
-                            header,
-                            len: cursor,
-                        }));
-                    }
-                } else {
-                    // Continue (C) bit is one
-                    cursor += 1;
-                }
+            let (bits, len, continued) = read_continued(&input[1..], 6).map_err(Either::Right)?;
+
+            // payloads are either 4 bytes (48-bit timestamps) or 6 bytes (64-bit timestamps) in
+            // size. Like with LTS1 we consider a packet whose sixth payload byte has the C bit set
+            // to end at the fifth payload byte
+            let b64 = match len {
+                4 if !continued && bits >> 22 == 0 => false,
+                6 if !continued && bits >> 38 == 0 => true,
+                _ => return Err(Either::Left(Error::MalformedPacket { header, len })),
             };
 
             Ok(Packet::GTS2(GTS2 { bits, b64 }))
@@ -462,6 +1019,29 @@ fn parse(input: &[u8]) -> Result<Packet, Either<Error, NeedMoreBytes>> {
             Ok(Packet::StimulusPortPage(StimulusPortPage { page }))
         }
 
+        Header::Extension { c, ex, sh } => {
+            let mut ex = u32::from(ex);
+            let mut len = 1;
+            if c {
+                let (payload, n, continued) =
+                    read_continued(&input[1..], 3).map_err(Either::Right)?;
+                ex += (payload as u32) << 3;
+                len += n;
+
+                if continued {
+                    // the fourth payload byte has no C bit; all its bits are EX bits
+                    let payload = input
+                        .get(usize::from(len))
+                        .cloned()
+                        .ok_or(Either::Right(NeedMoreBytes))?;
+                    ex += u32::from(payload) << 24;
+                    len += 1;
+                }
+            }
+
+            Ok(Packet::Extension(Extension { ex, len, sh }))
+        }
+
         Header::EventCounter => {
             let payload = input.get(1).cloned().ok_or(Either::Right(NeedMoreBytes))?;
 
@@ -558,6 +1138,31 @@ fn parse(input: &[u8]) -> Result<Packet, Either<Error, NeedMoreBytes>> {
     }
 }
 
+// Reads the payload of a variable-length packet; i.e. the bytes up to, and including, the first one
+// whose C (Continue) bit is zero, but no more than `max` bytes
+//
+// Returns the 7-bit fields of the payload bytes, the first byte being the least significant, the
+// number of payload bytes and whether the C bit of the last payload byte is set -- this can only
+// be the case when `max` bytes have been read
+fn read_continued(payload: &[u8], max: u8) -> Result<(u64, u8, bool), NeedMoreBytes> {
+    let mut value = 0;
+    let mut len = 0;
+    loop {
+        let byte = payload
+            .get(usize::from(len))
+            .cloned()
+            .ok_or(NeedMoreBytes)?;
+
+        value |= u64::from(byte & 0b0111_1111) << (7 * len);
+        len += 1;
+
+        let continued = byte & 0b1000_0000 != 0;
+        if !continued || len == max {
+            return Ok((value, len, continued));
+        }
+    }
+}
+
 struct NeedMoreBytes;
 
 #[derive(Debug)]
@@ -591,6 +1196,12 @@ enum Header {
     StimulusPortPage {
         page: u8,
     },
+    /// D4.2.6 Extension packet (any other form)
+    Extension {
+        c: bool,
+        ex: u8,
+        sh: bool,
+    },
 
     // D4.3 Hardware Source Packets
     /// D4.3.1 Event counter packet
@@ -654,10 +1265,17 @@ impl Header {
                     let page = (byte >> 4) & 0b111;
 
                     Header::StimulusPortPage { page }
+                } else if byte & 0b0000_1011 == 0b0000_1000 {
+                    // 0bCxxx_1S00
+                    let c = byte & 0b1000_0000 != 0;
+                    let ex = (byte >> 4) & 0b111;
+                    let sh = byte & 0b0000_0100 != 0;
+
+                    Header::Extension { c, ex, sh }
                 } else {
                     // 0bAAAA_A0SS
                     match byte & 0b111 {
-                        0b001 | 0b010 | 0b011 => {
+                        0b001..=0b011 => {
                             let port = byte >> 3;
                             let size = match byte & 0b11 {
                                 0b01 => 1,
@@ -677,9 +1295,9 @@ impl Header {
                                 // 0b01xx_1110
                                 Header::DataTraceAddress { cmpn }
                             } else if byte & 0b1100_0100 == 0b1000_0100 {
-                                // 0b01xx_W1SS
+                                // 0b10xx_W1SS
                                 match byte & 0b11 {
-                                    0b01 | 0b10 | 0b11 => {
+                                    0b01..=0b11 => {
                                         let size = match byte & 0b11 {
                                             0b01 => 1,
                                             0b10 => 2,
@@ -2,22 +2,39 @@
 
 use core::fmt;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 /// Synchronization packet
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(try_from = "repr::Synchronization", into = "repr::Synchronization")
+)]
 pub struct Synchronization {
     pub(crate) len: u8,
 }
 
+#[allow(clippy::len_without_is_empty)]
 impl Synchronization {
     /// The length in bytes of this synchronization packet
+    ///
+    /// This saturates at `u8::MAX` for longer synchronization packets
     pub fn len(&self) -> u8 {
         self.len
     }
 }
 
 /// Instrumentation packet
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(from = "repr::Instrumentation", into = "repr::Instrumentation")
+)]
 pub struct Instrumentation {
+    // NOTE bytes past `size` are always zero so the derived `PartialEq` only compares the payload
     pub(crate) buffer: [u8; 4],
     pub(crate) port: u8,
     pub(crate) size: u8,
@@ -25,6 +42,10 @@ pub struct Instrumentation {
 
 impl Instrumentation {
     /// The stimulus port that generated this packet
+    ///
+    /// `Stream` and `Decoder` take into account the stimulus port page selected by the last
+    /// `StimulusPortPage` packet, so this is in the range `0..=255`. The `decode` function has no
+    /// such state and always returns ports in the range `0..=31`.
     pub fn port(&self) -> u8 {
         self.port
     }
@@ -46,6 +67,11 @@ impl fmt::Debug for Instrumentation {
 
 /// Local timestamp packet
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(try_from = "repr::LocalTimestamp", into = "repr::LocalTimestamp")
+)]
 pub struct LocalTimestamp {
     pub(crate) delta: u32,
     // TC[1:0] bits
@@ -89,10 +115,44 @@ impl LocalTimestamp {
     pub fn event_delayed(&self) -> bool {
         self.tc & 0b10 == 0b10
     }
+
+    /// How the local timestamp value relates to the corresponding ITM or DWT data
+    pub fn control(&self) -> TimestampControl {
+        match self.tc {
+            0b00 => TimestampControl::Synced,
+            0b01 => TimestampControl::TimestampDelayed,
+            0b10 => TimestampControl::PacketDelayed,
+            _ => TimestampControl::BothDelayed,
+        }
+    }
+}
+
+// NOTE the same value can be encoded in packets of different sizes so `len` is neither compared nor
+// serialized; the same goes for `GTS1` and `Extension`
+impl PartialEq for LocalTimestamp {
+    fn eq(&self, other: &LocalTimestamp) -> bool {
+        self.delta == other.delta && self.tc == other.tc
+    }
+}
+
+/// The `TC[1:0]` field of a Local timestamp packet
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TimestampControl {
+    /// The timestamp value is synchronous to the ITM or DWT data
+    Synced,
+    /// The timestamp value is delayed relative to the ITM or DWT data
+    TimestampDelayed,
+    /// The ITM or DWT packet is delayed relative to the associated event
+    PacketDelayed,
+    /// Both the timestamp value and the ITM or DWT packet are delayed
+    BothDelayed,
 }
 
 /// Global timestamp packet (format 1)
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "repr::GTS1", into = "repr::GTS1"))]
 pub struct GTS1 {
     pub(crate) bits: u32,
     pub(crate) clk_ch: bool,
@@ -116,15 +176,23 @@ impl GTS1 {
         self.clk_ch
     }
 
-    /// The value of global timestamp bits TS[47:26] or TS[63:26] have changed since the last GTS2
-    /// packet output by the ITM
+    /// The value of global timestamp bits `TS[47:26]` or `TS[63:26]` have changed since the last
+    /// GTS2 packet output by the ITM
     pub fn has_wrapped(&self) -> bool {
         self.wrap
     }
 }
 
+impl PartialEq for GTS1 {
+    fn eq(&self, other: &GTS1) -> bool {
+        self.bits == other.bits && self.clk_ch == other.clk_ch && self.wrap == other.wrap
+    }
+}
+
 /// Global timestamp packet (format 2)
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "repr::GTS2", into = "repr::GTS2"))]
 pub struct GTS2 {
     pub(crate) bits: u64,
     pub(crate) b64: bool,
@@ -143,7 +211,12 @@ impl GTS2 {
 }
 
 /// Stimulus Port Page (Extension packet)
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(try_from = "repr::StimulusPortPage", into = "repr::StimulusPortPage")
+)]
 pub struct StimulusPortPage {
     pub(crate) page: u8,
 }
@@ -155,8 +228,48 @@ impl StimulusPortPage {
     }
 }
 
-/// Event counter packet
+/// Extension packet
+///
+/// The single byte extension packet that the ITM uses to select the stimulus port page is decoded
+/// as `StimulusPortPage` instead
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(try_from = "repr::Extension", into = "repr::Extension")
+)]
+pub struct Extension {
+    pub(crate) ex: u32,
+    // Size of this packet in bytes, including the header
+    pub(crate) len: u8,
+    pub(crate) sh: bool,
+}
+
+impl Extension {
+    /// Extension information (up to 32 bits)
+    pub fn ex(&self) -> u32 {
+        self.ex
+    }
+
+    /// The extension information is for a hardware source (DWT) rather than for the ITM
+    pub fn is_hardware_source(&self) -> bool {
+        self.sh
+    }
+}
+
+impl PartialEq for Extension {
+    fn eq(&self, other: &Extension) -> bool {
+        self.ex == other.ex && self.sh == other.sh
+    }
+}
+
+/// Event counter packet
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(from = "repr::EventCounter", into = "repr::EventCounter")
+)]
 pub struct EventCounter {
     pub(crate) payload: u8,
 }
@@ -195,6 +308,7 @@ impl EventCounter {
 
 /// The action taken by the processor
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Function {
     /// Entered exception
     Enter,
@@ -205,7 +319,12 @@ pub enum Function {
 }
 
 /// Exception trace packet
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(try_from = "repr::ExceptionTrace", into = "repr::ExceptionTrace")
+)]
 pub struct ExceptionTrace {
     pub(crate) function: Function,
     pub(crate) number: u16,
@@ -224,7 +343,8 @@ impl ExceptionTrace {
 }
 
 /// Periodic PC sample packet
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PeriodicPcSample {
     pub(crate) pc: Option<u32>,
 }
@@ -239,7 +359,12 @@ impl PeriodicPcSample {
 }
 
 /// Data trace PC packet
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(try_from = "repr::DataTracePcValue", into = "repr::DataTracePcValue")
+)]
 pub struct DataTracePcValue {
     pub(crate) cmpn: u8,
     pub(crate) pc: u32,
@@ -258,7 +383,12 @@ impl DataTracePcValue {
 }
 
 /// Data trace address packet
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(try_from = "repr::DataTraceAddress", into = "repr::DataTraceAddress")
+)]
 pub struct DataTraceAddress {
     pub(crate) cmpn: u8,
     pub(crate) address: u16,
@@ -277,8 +407,17 @@ impl DataTraceAddress {
 }
 
 /// Data trace data value packet
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(
+        try_from = "repr::DataTraceDataValue",
+        into = "repr::DataTraceDataValue"
+    )
+)]
 pub struct DataTraceDataValue {
+    // NOTE bytes past `size` are always zero so the derived `PartialEq` only compares the value
     pub(crate) buffer: [u8; 4],
     pub(crate) cmpn: u8,
     pub(crate) size: u8,
@@ -316,3 +455,482 @@ impl DataTraceDataValue {
         self.wnr
     }
 }
+
+#[cfg(feature = "serde")]
+mod repr {
+    //! Serialization formats of the packets
+    //!
+    //! Packets are (de)serialized as the values that their accessors return, rather than as their
+    //! internal representation. Variable sized payloads are (de)serialized as hex strings, and
+    //! deserialization rejects values that no ITM packet can encode.
+
+    use core::convert::TryFrom;
+    use core::fmt;
+
+    use serde::de::{self, Visitor};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Clone, Copy)]
+    pub struct Payload {
+        buffer: [u8; 4],
+        size: u8,
+    }
+
+    impl Serialize for Payload {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serializer.collect_str(self)
+        }
+    }
+
+    impl fmt::Display for Payload {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            for byte in &self.buffer[..usize::from(self.size)] {
+                write!(f, "{:02x}", byte)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Payload {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserializer.deserialize_str(PayloadVisitor)
+        }
+    }
+
+    struct PayloadVisitor;
+
+    impl<'de> Visitor<'de> for PayloadVisitor {
+        type Value = Payload;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a hex string encoding 1, 2 or 4 bytes")
+        }
+
+        fn visit_str<E>(self, s: &str) -> Result<Payload, E>
+        where
+            E: de::Error,
+        {
+            let size = match s.len() {
+                2 => 1,
+                4 => 2,
+                8 => 4,
+                _ => return Err(E::invalid_length(s.len() / 2, &self)),
+            };
+
+            let mut buffer = [0; 4];
+            for (byte, digits) in buffer.iter_mut().zip(s.as_bytes().chunks(2)) {
+                *byte = core::str::from_utf8(digits)
+                    .ok()
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                    .ok_or_else(|| E::invalid_value(de::Unexpected::Str(s), &self))?;
+            }
+
+            Ok(Payload { buffer, size })
+        }
+    }
+
+    #[derive(Clone, Copy, Deserialize, Serialize)]
+    pub struct Instrumentation {
+        payload: Payload,
+        port: u8,
+    }
+
+    impl From<super::Instrumentation> for Instrumentation {
+        fn from(i: super::Instrumentation) -> Self {
+            Instrumentation {
+                payload: Payload {
+                    buffer: i.buffer,
+                    size: i.size,
+                },
+                port: i.port,
+            }
+        }
+    }
+
+    impl From<Instrumentation> for super::Instrumentation {
+        fn from(i: Instrumentation) -> Self {
+            super::Instrumentation {
+                buffer: i.payload.buffer,
+                port: i.port,
+                size: i.payload.size,
+            }
+        }
+    }
+
+    /// Returns the number of 7-bit continuation groups needed to encode `value`
+    fn groups(value: u64) -> u8 {
+        let mut n = 1;
+        while value >> (7 * n) != 0 {
+            n += 1;
+        }
+        n
+    }
+
+    /// Checks that `cmpn` is the number of one of the four DWT comparators
+    fn comparator(cmpn: u8) -> Result<u8, &'static str> {
+        if cmpn < 4 {
+            Ok(cmpn)
+        } else {
+            Err("comparator is out of range (0..=3)")
+        }
+    }
+
+    #[derive(Clone, Copy, Deserialize, Serialize)]
+    pub struct Synchronization {
+        len: u8,
+    }
+
+    impl From<super::Synchronization> for Synchronization {
+        fn from(s: super::Synchronization) -> Self {
+            Synchronization { len: s.len }
+        }
+    }
+
+    impl TryFrom<Synchronization> for super::Synchronization {
+        type Error = &'static str;
+
+        fn try_from(s: Synchronization) -> Result<Self, &'static str> {
+            // at least forty-seven 0 bits followed by a single 1 bit
+            if s.len < 6 {
+                return Err("synchronization packets are at least 6 bytes long");
+            }
+
+            Ok(super::Synchronization { len: s.len })
+        }
+    }
+
+    #[derive(Clone, Copy, Deserialize, Serialize)]
+    pub struct LocalTimestamp {
+        control: super::TimestampControl,
+        delta: u32,
+    }
+
+    impl From<super::LocalTimestamp> for LocalTimestamp {
+        fn from(lt: super::LocalTimestamp) -> Self {
+            LocalTimestamp {
+                control: lt.control(),
+                delta: lt.delta,
+            }
+        }
+    }
+
+    impl TryFrom<LocalTimestamp> for super::LocalTimestamp {
+        type Error = &'static str;
+
+        fn try_from(lt: LocalTimestamp) -> Result<Self, &'static str> {
+            use super::TimestampControl::*;
+
+            if lt.delta >= 1 << 28 {
+                return Err("local timestamp delta is out of range (up to 28 bits)");
+            }
+
+            let tc = match lt.control {
+                Synced => 0b00,
+                TimestampDelayed => 0b01,
+                PacketDelayed => 0b10,
+                BothDelayed => 0b11,
+            };
+
+            // the shortest encoding: the single byte format 2, if possible, or format 1
+            let len = if tc == 0 && lt.delta != 0 && lt.delta < 7 {
+                1
+            } else {
+                1 + groups(u64::from(lt.delta))
+            };
+
+            Ok(super::LocalTimestamp {
+                delta: lt.delta,
+                tc,
+                len,
+            })
+        }
+    }
+
+    #[derive(Clone, Copy, Deserialize, Serialize)]
+    pub struct GTS1 {
+        bits: u32,
+        clock_changed: bool,
+        wrapped: bool,
+    }
+
+    impl From<super::GTS1> for GTS1 {
+        fn from(gt: super::GTS1) -> Self {
+            GTS1 {
+                bits: gt.bits,
+                clock_changed: gt.clk_ch,
+                wrapped: gt.wrap,
+            }
+        }
+    }
+
+    impl TryFrom<GTS1> for super::GTS1 {
+        type Error = &'static str;
+
+        fn try_from(gt: GTS1) -> Result<Self, &'static str> {
+            if gt.bits >= 1 << 26 {
+                return Err("global timestamp bits are out of range (up to 26 bits)");
+            }
+
+            // the ClkCh and Wrap flags are only present in the 5 byte encoding
+            let len = if gt.clock_changed || gt.wrapped {
+                5
+            } else {
+                1 + groups(u64::from(gt.bits))
+            };
+
+            Ok(super::GTS1 {
+                bits: gt.bits,
+                clk_ch: gt.clock_changed,
+                len,
+                wrap: gt.wrapped,
+            })
+        }
+    }
+
+    #[derive(Clone, Copy, Deserialize, Serialize)]
+    pub struct GTS2 {
+        bits: u64,
+        is_64_bit: bool,
+    }
+
+    impl From<super::GTS2> for GTS2 {
+        fn from(gt: super::GTS2) -> Self {
+            GTS2 {
+                bits: gt.bits,
+                is_64_bit: gt.b64,
+            }
+        }
+    }
+
+    impl TryFrom<GTS2> for super::GTS2 {
+        type Error = &'static str;
+
+        fn try_from(gt: GTS2) -> Result<Self, &'static str> {
+            // TS[47:26] or TS[63:26]
+            let width = if gt.is_64_bit { 38 } else { 22 };
+            if gt.bits >= 1 << width {
+                return Err("global timestamp bits are out of range (up to 22 or 38 bits)");
+            }
+
+            Ok(super::GTS2 {
+                bits: gt.bits,
+                b64: gt.is_64_bit,
+            })
+        }
+    }
+
+    #[derive(Clone, Copy, Deserialize, Serialize)]
+    pub struct StimulusPortPage {
+        page: u8,
+    }
+
+    impl From<super::StimulusPortPage> for StimulusPortPage {
+        fn from(spp: super::StimulusPortPage) -> Self {
+            StimulusPortPage { page: spp.page }
+        }
+    }
+
+    impl TryFrom<StimulusPortPage> for super::StimulusPortPage {
+        type Error = &'static str;
+
+        fn try_from(spp: StimulusPortPage) -> Result<Self, &'static str> {
+            if spp.page >= 8 {
+                return Err("stimulus port page is out of range (0..=7)");
+            }
+
+            Ok(super::StimulusPortPage { page: spp.page })
+        }
+    }
+
+    #[derive(Clone, Copy, Deserialize, Serialize)]
+    pub struct Extension {
+        ex: u32,
+        is_hardware_source: bool,
+    }
+
+    impl From<super::Extension> for Extension {
+        fn from(e: super::Extension) -> Self {
+            Extension {
+                ex: e.ex,
+                is_hardware_source: e.sh,
+            }
+        }
+    }
+
+    impl TryFrom<Extension> for super::Extension {
+        type Error = &'static str;
+
+        fn try_from(e: Extension) -> Result<Self, &'static str> {
+            // the header carries 3 bits of information, the payload up to 4 more bytes, the last
+            // of which carries 8 bits. A single byte ITM extension packet is a `StimulusPortPage`
+            // packet so an ITM extension packet has at least one payload byte
+            let len = if e.ex < 8 && e.is_hardware_source {
+                1
+            } else {
+                1 + groups(u64::from(e.ex >> 3)).min(4)
+            };
+
+            Ok(super::Extension {
+                ex: e.ex,
+                len,
+                sh: e.is_hardware_source,
+            })
+        }
+    }
+
+    #[derive(Clone, Copy, Deserialize, Serialize)]
+    pub struct EventCounter {
+        cpi: bool,
+        exc: bool,
+        sleep: bool,
+        lsu: bool,
+        fold: bool,
+        post: bool,
+    }
+
+    impl From<super::EventCounter> for EventCounter {
+        fn from(ec: super::EventCounter) -> Self {
+            EventCounter {
+                cpi: ec.cpi(),
+                exc: ec.exc(),
+                sleep: ec.sleep(),
+                lsu: ec.lsu(),
+                fold: ec.fold(),
+                post: ec.post(),
+            }
+        }
+    }
+
+    impl From<EventCounter> for super::EventCounter {
+        fn from(ec: EventCounter) -> Self {
+            let payload = [ec.cpi, ec.exc, ec.sleep, ec.lsu, ec.fold, ec.post]
+                .iter()
+                .enumerate()
+                .fold(0, |payload, (i, &bit)| payload | (u8::from(bit) << i));
+
+            super::EventCounter { payload }
+        }
+    }
+
+    #[derive(Clone, Copy, Deserialize, Serialize)]
+    pub struct ExceptionTrace {
+        function: super::Function,
+        number: u16,
+    }
+
+    impl From<super::ExceptionTrace> for ExceptionTrace {
+        fn from(et: super::ExceptionTrace) -> Self {
+            ExceptionTrace {
+                function: et.function,
+                number: et.number,
+            }
+        }
+    }
+
+    impl TryFrom<ExceptionTrace> for super::ExceptionTrace {
+        type Error = &'static str;
+
+        fn try_from(et: ExceptionTrace) -> Result<Self, &'static str> {
+            if et.number >= 1 << 9 {
+                return Err("exception number is out of range (up to 9 bits)");
+            }
+
+            Ok(super::ExceptionTrace {
+                function: et.function,
+                number: et.number,
+            })
+        }
+    }
+
+    #[derive(Clone, Copy, Deserialize, Serialize)]
+    pub struct DataTracePcValue {
+        comparator: u8,
+        pc: u32,
+    }
+
+    impl From<super::DataTracePcValue> for DataTracePcValue {
+        fn from(dtpc: super::DataTracePcValue) -> Self {
+            DataTracePcValue {
+                comparator: dtpc.cmpn,
+                pc: dtpc.pc,
+            }
+        }
+    }
+
+    impl TryFrom<DataTracePcValue> for super::DataTracePcValue {
+        type Error = &'static str;
+
+        fn try_from(dtpc: DataTracePcValue) -> Result<Self, &'static str> {
+            Ok(super::DataTracePcValue {
+                cmpn: comparator(dtpc.comparator)?,
+                pc: dtpc.pc,
+            })
+        }
+    }
+
+    #[derive(Clone, Copy, Deserialize, Serialize)]
+    pub struct DataTraceAddress {
+        address: u16,
+        comparator: u8,
+    }
+
+    impl From<super::DataTraceAddress> for DataTraceAddress {
+        fn from(dta: super::DataTraceAddress) -> Self {
+            DataTraceAddress {
+                address: dta.address,
+                comparator: dta.cmpn,
+            }
+        }
+    }
+
+    impl TryFrom<DataTraceAddress> for super::DataTraceAddress {
+        type Error = &'static str;
+
+        fn try_from(dta: DataTraceAddress) -> Result<Self, &'static str> {
+            Ok(super::DataTraceAddress {
+                address: dta.address,
+                cmpn: comparator(dta.comparator)?,
+            })
+        }
+    }
+
+    #[derive(Clone, Copy, Deserialize, Serialize)]
+    pub struct DataTraceDataValue {
+        comparator: u8,
+        value: Payload,
+        write_access: bool,
+    }
+
+    impl From<super::DataTraceDataValue> for DataTraceDataValue {
+        fn from(dtdv: super::DataTraceDataValue) -> Self {
+            DataTraceDataValue {
+                comparator: dtdv.cmpn,
+                value: Payload {
+                    buffer: dtdv.buffer,
+                    size: dtdv.size,
+                },
+                write_access: dtdv.wnr,
+            }
+        }
+    }
+
+    impl TryFrom<DataTraceDataValue> for super::DataTraceDataValue {
+        type Error = &'static str;
+
+        fn try_from(dtdv: DataTraceDataValue) -> Result<Self, &'static str> {
+            Ok(super::DataTraceDataValue {
+                buffer: dtdv.value.buffer,
+                cmpn: comparator(dtdv.comparator)?,
+                size: dtdv.value.size,
+                wnr: dtdv.write_access,
+            })
+        }
+    }
+}
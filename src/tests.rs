@@ -1,6 +1,10 @@
-use std::io::Cursor;
+use std::io::{self, Cursor, Read};
 
-use crate::{packet::Function, Error, Packet, Stream};
+use crate::{
+    decode,
+    packet::{Function, TimestampControl},
+    tpiu, Decoder, Error, Packet, PacketKind, StimulusReader, Stream,
+};
 
 #[test]
 fn synchronization() {
@@ -106,6 +110,111 @@ fn instrumentation() {
     assert!(stream.next().unwrap().is_none());
 }
 
+#[test]
+fn highest_stimulus_port() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // port 31; 1 byte
+            0xf9, 0x10, //
+            // port 31; 4 bytes
+            0xfb, 0x70, 0x60, 0x50, 0x40,
+        ]),
+        false,
+    );
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => {
+            assert_eq!(i.port(), 31);
+            assert_eq!(i.payload(), &[0x10]);
+        }
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => {
+            assert_eq!(i.port(), 31);
+            assert_eq!(i.payload(), &[0x70, 0x60, 0x50, 0x40]);
+        }
+        _ => panic!(),
+    }
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
+#[test]
+fn interleaved_ports() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // port 0; 1 byte
+            0x01, b'H', //
+            // port 1; 4 bytes
+            0x0b, 0x01, 0x09, 0x0a, 0x0b, //
+            // port 0; 2 bytes
+            0x02, b'e', b'l', //
+            // port 1; 2 bytes
+            0x0a, 0x02, 0x01, //
+            // port 0; 4 bytes
+            0x03, b'l', b'o', b'\n', 0x00, //
+            // port 1; 1 byte
+            0x09, 0x07,
+        ]),
+        false,
+    );
+
+    let mut port0 = vec![];
+    let mut port1 = vec![];
+    while let Some(packet) = stream.next().unwrap() {
+        match packet.unwrap() {
+            Packet::Instrumentation(i) if i.port() == 0 => port0.extend_from_slice(i.payload()),
+            Packet::Instrumentation(i) if i.port() == 1 => port1.extend_from_slice(i.payload()),
+            _ => panic!(),
+        }
+    }
+
+    assert_eq!(port0, b"Hello\n\0");
+    assert_eq!(port1, &[0x01, 0x09, 0x0a, 0x0b, 0x02, 0x01, 0x07]);
+}
+
+#[test]
+fn stimulus_reader() {
+    let bytes = &[
+        // port 0; 2 bytes
+        0x02, b'H', b'i', //
+        // port 1; 1 byte
+        0x09, 0x07, //
+        // Overflow
+        0x70, //
+        // reserved
+        0x04, //
+        // port 0; 4 bytes
+        0x03, b'!', b'!', b'\n', 0x00,
+    ];
+
+    let mut data = vec![];
+    StimulusReader::new(Stream::new(Cursor::new(bytes), false), 0)
+        .read_to_end(&mut data)
+        .unwrap();
+    assert_eq!(data, b"Hi!!\n\0");
+
+    // small reads
+    let mut reader = StimulusReader::new(Stream::new(Cursor::new(bytes), false), 0);
+    let mut buf = [0; 3];
+    assert_eq!(reader.read(&mut buf).unwrap(), 2);
+    assert_eq!(&buf[..2], b"Hi");
+    assert_eq!(reader.read(&mut buf).unwrap(), 3);
+    assert_eq!(&buf, b"!!\n");
+    assert_eq!(reader.read(&mut buf).unwrap(), 1);
+    assert_eq!(&buf[..1], b"\0");
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+    let mut data = vec![];
+    StimulusReader::new(Stream::new(Cursor::new(bytes), false), 1)
+        .read_to_end(&mut data)
+        .unwrap();
+    assert_eq!(data, &[0x07]);
+}
+
 #[test]
 fn lts1() {
     let mut stream = Stream::new(
@@ -186,6 +295,162 @@ fn lts1() {
     assert!(stream.next().unwrap().is_none());
 }
 
+#[test]
+fn lts_control() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // LTS1; TC = 0b00
+            0xc0, 0x05, //
+            // LTS2
+            0x30,
+        ]),
+        false,
+    );
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::LocalTimestamp(lt) => {
+            assert_eq!(lt.control(), TimestampControl::Synced);
+            assert_eq!(lt.delta(), 5);
+        }
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::LocalTimestamp(lt) => {
+            assert_eq!(lt.control(), TimestampControl::Synced);
+            assert_eq!(lt.delta(), 3);
+        }
+        _ => panic!(),
+    }
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
+#[test]
+fn read_continued() {
+    use crate::read_continued;
+
+    let cases: &[(&[u8], u64)] = &[
+        (&[0x05], 5),
+        (&[0x85, 0x01], 5 + (1 << 7)),
+        (&[0x85, 0x81, 0x01], 5 + (1 << 7) + (1 << 14)),
+        (
+            &[0x85, 0x81, 0x81, 0x7f],
+            5 + (1 << 7) + (1 << 14) + (0x7f << 21),
+        ),
+        (&[0xff, 0xff, 0xff, 0xff, 0x7f], (1 << 35) - 1),
+    ];
+
+    for &(payload, value) in cases {
+        // trailing bytes are not part of the payload
+        let mut input = payload.to_vec();
+        input.push(0xff);
+
+        assert_eq!(
+            read_continued(&input, 6).ok(),
+            Some((value, payload.len() as u8, false))
+        );
+    }
+
+    // the C bit of the last byte is set
+    assert_eq!(
+        read_continued(&[0x81, 0x81, 0x81, 0x81], 4).ok(),
+        Some((1 + (1 << 7) + (1 << 14) + (1 << 21), 4, true))
+    );
+
+    // need more bytes
+    assert!(read_continued(&[0x81, 0x81], 4).is_err());
+    assert!(read_continued(&[], 4).is_err());
+}
+
+#[test]
+fn lts1_tc() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // LTS1; TC = 0b01
+            0xd0, 0x05, //
+            // LTS1; TC = 0b10
+            0xe0, 0x85, 0x01, //
+            // LTS1; TC = 0b11
+            0xf0, 0x7f,
+        ]),
+        false,
+    );
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::LocalTimestamp(lt) => {
+            assert!(!lt.is_precise());
+            assert!(lt.timestamp_delayed());
+            assert!(!lt.event_delayed());
+            assert_eq!(lt.control(), TimestampControl::TimestampDelayed);
+            assert_eq!(lt.delta(), 5);
+        }
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::LocalTimestamp(lt) => {
+            assert!(!lt.is_precise());
+            assert!(!lt.timestamp_delayed());
+            assert!(lt.event_delayed());
+            assert_eq!(lt.control(), TimestampControl::PacketDelayed);
+            assert_eq!(lt.delta(), 5 + (1 << 7));
+        }
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::LocalTimestamp(lt) => {
+            assert!(!lt.is_precise());
+            assert!(lt.timestamp_delayed());
+            assert!(lt.event_delayed());
+            assert_eq!(lt.control(), TimestampControl::BothDelayed);
+            assert_eq!(lt.delta(), 0x7f);
+        }
+        _ => panic!(),
+    }
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
+#[test]
+fn lts1_malformed() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // LTS1; the C bit of the last payload byte is set
+            0xc0, 0x81, 0x81, 0x81, 0x80, //
+            // Instrumentation
+            0x01, 0x10,
+        ]),
+        false,
+    );
+
+    // the packet is considered to end at the third payload byte
+    match stream.next().unwrap().unwrap() {
+        Err(Error::MalformedPacket { header, len }) => {
+            assert_eq!(header, 0xc0);
+            assert_eq!(len, 4);
+        }
+        _ => panic!(),
+    }
+
+    // the last payload byte is a reserved header
+    match stream.next().unwrap().unwrap() {
+        Err(Error::ReservedHeader { byte }) => assert_eq!(byte, 0x80),
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.payload(), &[0x10]),
+        _ => panic!(),
+    }
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
 #[test]
 fn lts2() {
     let mut stream = Stream::new(
@@ -331,6 +596,41 @@ fn gts2() {
     assert!(stream.next().unwrap().is_none());
 }
 
+#[test]
+fn global_timestamp() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // GTS2; TS[47:26]
+            0xb4, 0x95, 0x9a, 0x92, 0x00, //
+            // GTS1; TS[25:0]
+            0x94, 0xbc, 0xb5, 0xe2, 0x13,
+        ]),
+        false,
+    );
+
+    let high = match stream.next().unwrap().unwrap().unwrap() {
+        Packet::GTS2(gt) => {
+            assert!(!gt.is_64_bit());
+            gt.bits()
+        }
+        _ => panic!(),
+    };
+
+    let low = match stream.next().unwrap().unwrap().unwrap() {
+        Packet::GTS1(gt) => {
+            assert!(!gt.has_clock_changed());
+            assert!(!gt.has_wrapped());
+            gt.bits()
+        }
+        _ => panic!(),
+    };
+
+    assert_eq!((high << 26) | u64::from(low), 0x1234_5678_9abc);
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
 #[test]
 fn stimulus_port_page() {
     let mut stream = Stream::new(
@@ -352,6 +652,118 @@ fn stimulus_port_page() {
     assert!(stream.next().unwrap().is_none());
 }
 
+#[test]
+fn paged_stimulus_port() {
+    let bytes = [
+        // Stimulus Port Page; page 1
+        0x18, //
+        // Instrumentation; port 2
+        0x11, 0x41, //
+        // Stimulus Port Page; page 7
+        0x78, //
+        // Instrumentation; port 31
+        0xf9, 0x42,
+    ];
+    let mut stream = Stream::new(Cursor::new(&bytes), false);
+
+    match stream.nth(1).unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.port(), 34),
+        _ => panic!(),
+    }
+
+    match stream.nth(1).unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.port(), 255),
+        _ => panic!(),
+    }
+
+    // `reset` selects page 0
+    let mut stream = Stream::new(
+        Chunks {
+            chunks: vec![&[0x18], &[], &[0x11, 0x41]],
+        },
+        false,
+    );
+    assert!(stream.next().unwrap().unwrap().is_ok());
+    assert!(stream.next().unwrap().is_none());
+    stream.reset();
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.port(), 2),
+        _ => panic!(),
+    }
+
+    let mut decoder = Decoder::new();
+    decoder.feed(&bytes);
+    decoder.next_packet();
+    match decoder.next_packet().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.port(), 34),
+        _ => panic!(),
+    }
+
+    // `reset` discards the buffered bytes and selects page 0
+    decoder.feed(&bytes[..2]);
+    decoder.reset();
+    decoder.feed(&bytes[1..3]);
+    match decoder.next_packet().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.port(), 2),
+        _ => panic!(),
+    }
+
+    // `decode` has no state
+    match decode(&bytes[1..]).unwrap().0.unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.port(), 2),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn extension() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // hardware source; 1 byte
+            0x3c, //
+            // hardware source; 3 bytes
+            0x8c, 0x81, 0x01, //
+            // ITM; 5 bytes
+            0xf8, 0xff, 0xff, 0xff, 0xff, //
+            // Stimulus Port Page
+            0x18,
+        ]),
+        false,
+    );
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Extension(e) => {
+            assert!(e.is_hardware_source());
+            assert_eq!(e.ex(), 3);
+        }
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Extension(e) => {
+            assert!(e.is_hardware_source());
+            assert_eq!(e.ex(), (1 << 3) + (1 << 10));
+        }
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Extension(e) => {
+            assert!(!e.is_hardware_source());
+            assert_eq!(e.ex(), 0xffff_ffff);
+        }
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::StimulusPortPage(spp) => assert_eq!(spp.page(), 1),
+        _ => panic!(),
+    }
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
 #[test]
 fn event_counter() {
     let mut stream = Stream::new(
@@ -416,6 +828,28 @@ fn exception_trace() {
     assert!(stream.next().unwrap().is_none());
 }
 
+#[test]
+fn exception_trace_9_bit_number() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // Exception Trace; 9-bit exception number
+            0x0e, 0x2c, 0x31,
+        ]),
+        false,
+    );
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::ExceptionTrace(et) => {
+            assert_eq!(et.number(), 300);
+            assert_eq!(et.function(), Function::Return);
+        }
+        _ => panic!(),
+    }
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
 #[test]
 fn periodic_pc_sample() {
     let mut stream = Stream::new(
@@ -490,6 +924,28 @@ fn data_trace_address() {
     assert!(stream.next().unwrap().is_none());
 }
 
+#[test]
+fn data_trace_address_comparator() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // Data Trace Address; comparator 3
+            0x7e, 0x00, 0x20,
+        ]),
+        false,
+    );
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::DataTraceAddress(pps) => {
+            assert_eq!(pps.comparator(), 3);
+            assert_eq!(pps.address(), 0x2000);
+        }
+        _ => panic!(),
+    }
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
 #[test]
 fn data_trace_data_value() {
     let mut stream = Stream::new(
@@ -512,3 +968,794 @@ fn data_trace_data_value() {
     // EOF
     assert!(stream.next().unwrap().is_none());
 }
+
+#[test]
+fn reserved_header() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // reserved
+            0x04, //
+            // Instrumentation
+            0x01, 0x10,
+        ]),
+        false,
+    );
+
+    match stream.next().unwrap().unwrap() {
+        Err(Error::ReservedHeader { byte }) => assert_eq!(byte, 0x04),
+        _ => panic!(),
+    }
+
+    // the reserved header is skipped and decoding resumes at the next byte
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => {
+            assert_eq!(i.port(), 0);
+            assert_eq!(i.payload(), &[0x10]);
+        }
+        _ => panic!(),
+    }
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
+#[test]
+fn iterator() {
+    let stream = Stream::new(
+        Cursor::new(&[
+            // Instrumentation
+            0x01, 0x10, //
+            // reserved
+            0x04, //
+            // Overflow
+            0x70, //
+            // truncated Instrumentation
+            0x02, 0x20,
+        ]),
+        false,
+    );
+
+    let packets = stream.map(|p| p.unwrap()).collect::<Vec<_>>();
+    assert_eq!(packets.len(), 4);
+
+    match packets[0] {
+        Ok(Packet::Instrumentation(i)) => assert_eq!(i.payload(), &[0x10]),
+        _ => panic!(),
+    }
+
+    match packets[1] {
+        Err(Error::ReservedHeader { byte }) => assert_eq!(byte, 0x04),
+        _ => panic!(),
+    }
+
+    match packets[2] {
+        Ok(Packet::Overflow) => {}
+        _ => panic!(),
+    }
+
+    match packets[3] {
+        Err(Error::MalformedPacket { header, len }) => {
+            assert_eq!(header, 0x02);
+            assert_eq!(len, 2);
+        }
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn kind() {
+    let count = Stream::new(
+        Cursor::new(&[
+            // Instrumentation
+            0x01, 0x41, //
+            // Overflow
+            0x70, //
+            // Instrumentation
+            0x01, 0x42,
+        ]),
+        false,
+    )
+    .map(|p| p.unwrap().unwrap())
+    .filter(|p| p.kind() == PacketKind::Instrumentation)
+    .count();
+
+    assert_eq!(count, 2);
+}
+
+#[test]
+fn decode_slice() {
+    // complete packet followed by the start of the next one
+    match decode(&[0x02, 0x10, 0x20, 0x70]) {
+        Some((Ok(Packet::Instrumentation(i)), len)) => {
+            assert_eq!(i.payload(), &[0x10, 0x20]);
+            assert_eq!(len, 3);
+        }
+        _ => panic!(),
+    }
+
+    // truncated packet
+    assert!(decode(&[0x03, 0x10, 0x20]).is_none());
+    assert!(decode(&[]).is_none());
+
+    // malformed packet
+    match decode(&[0x05, 0xff]) {
+        Some((Err(Error::MalformedPacket { header, len: 1 }), 1)) => assert_eq!(header, 0x05),
+        _ => panic!(),
+    }
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn ffi() {
+    use core::ffi::c_void;
+
+    use crate::ffi::{self, itm_packet_t};
+
+    // (kind, port, payload, bytes)
+    type Packets = Vec<(u32, u8, Vec<u8>, Vec<u8>)>;
+
+    extern "C" fn callback(context: *mut c_void, packet: *const itm_packet_t) {
+        let packets = unsafe { &mut *(context as *mut Packets) };
+        let packet = unsafe { &*packet };
+        packets.push((
+            packet.kind,
+            packet.port,
+            packet.payload[..usize::from(packet.payload_len)].to_owned(),
+            unsafe { std::slice::from_raw_parts(packet.bytes, packet.len) }.to_owned(),
+        ));
+    }
+
+    // a `NULL` callback is rejected
+    assert!(ffi::itm_decoder_new(None, core::ptr::null_mut()).is_null());
+
+    let mut packets: Packets = vec![];
+    let decoder = ffi::itm_decoder_new(Some(callback), &mut packets as *mut _ as *mut c_void);
+    unsafe {
+        // Instrumentation, split in two; Overflow
+        ffi::itm_decoder_feed(decoder, [0x0a, 0x30].as_ptr(), 2);
+        ffi::itm_decoder_feed(decoder, [0x20, 0x70].as_ptr(), 2);
+        // reserved; malformed Synchronization; Overflow; truncated Instrumentation
+        ffi::itm_decoder_feed(decoder, [0x04, 0x00, 0x00, 0x70, 0x03, 0x10].as_ptr(), 6);
+        ffi::itm_decoder_free(decoder);
+    }
+
+    assert_eq!(
+        packets,
+        [
+            (
+                ffi::ITM_PACKET_INSTRUMENTATION,
+                1,
+                vec![0x30, 0x20],
+                vec![0x0a, 0x30, 0x20]
+            ),
+            (ffi::ITM_PACKET_OVERFLOW, 0, vec![], vec![0x70]),
+            (ffi::ITM_PACKET_RESERVED_HEADER, 0, vec![], vec![0x04]),
+            (ffi::ITM_PACKET_MALFORMED, 0, vec![], vec![0x00, 0x00]),
+            (ffi::ITM_PACKET_OVERFLOW, 0, vec![], vec![0x70]),
+        ]
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde() {
+    let packets = Stream::new(
+        Cursor::new(&[
+            // Instrumentation
+            0x0a, 0x30, 0x20, //
+            // Data Trace Data Value
+            0x9f, 0x12, 0x34, 0x56, 0x78, //
+            // Local timestamp
+            0xd0, 0x81, 0x01, //
+            // Extension; continued stimulus port page
+            0x88, 0x00, //
+            // Overflow
+            0x70,
+        ]),
+        false,
+    )
+    .map(|p| p.unwrap().unwrap())
+    .collect::<Vec<_>>();
+
+    let json = serde_json::to_string(&packets).unwrap();
+    assert_eq!(
+        json,
+        r#"[{"Instrumentation":{"payload":"3020","port":1}},{"DataTraceDataValue":{"comparator":1,"value":"12345678","write_access":true}},{"LocalTimestamp":{"control":"TimestampDelayed","delta":129}},{"Extension":{"ex":0,"is_hardware_source":false}},"Overflow"]"#
+    );
+
+    let deserialized = serde_json::from_str::<Vec<Packet>>(&json).unwrap();
+    assert_eq!(deserialized, packets);
+    // the length of these packets is the one of their shortest encoding
+    assert_eq!(
+        deserialized.iter().map(Packet::len).collect::<Vec<_>>(),
+        packets.iter().map(Packet::len).collect::<Vec<_>>()
+    );
+
+    // only 1, 2 and 4 byte payloads are valid
+    assert!(
+        serde_json::from_str::<Packet>(r#"{"Instrumentation":{"payload":"302010","port":1}}"#)
+            .is_err()
+    );
+    assert!(
+        serde_json::from_str::<Packet>(r#"{"Instrumentation":{"payload":"zz","port":1}}"#).is_err()
+    );
+
+    // values that no packet can encode are rejected
+    assert!(serde_json::from_str::<Packet>(
+        r#"{"LocalTimestamp":{"control":"Synced","delta":268435456}}"#
+    )
+    .is_err());
+    assert!(serde_json::from_str::<Packet>(
+        r#"{"DataTraceAddress":{"address":4660,"comparator":4}}"#
+    )
+    .is_err());
+    assert!(serde_json::from_str::<Packet>(r#"{"StimulusPortPage":{"page":8}}"#).is_err());
+}
+
+#[test]
+fn offset() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // Instrumentation
+            0x02, 0x10, 0x20, //
+            // reserved
+            0x04, //
+            // Overflow
+            0x70, //
+            // truncated Instrumentation
+            0x03, 0x10,
+        ]),
+        false,
+    );
+
+    assert!(stream.next().unwrap().unwrap().is_ok());
+    assert_eq!(stream.offset(), 0);
+
+    assert!(stream.next().unwrap().unwrap().is_err());
+    assert_eq!(stream.offset(), 3);
+
+    assert!(stream.next().unwrap().unwrap().is_ok());
+    assert_eq!(stream.offset(), 4);
+
+    assert!(stream.next().unwrap().unwrap().is_err());
+    assert_eq!(stream.offset(), 5);
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
+#[test]
+fn stats() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // Synchronization
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x80, //
+            // Instrumentation, port 0
+            0x01, 0x61, //
+            // Instrumentation, port 2
+            0x13, 0x62, 0x63, 0x64, 0x65, //
+            // Overflow
+            0x70, //
+            // reserved
+            0x04, //
+            // Instrumentation, port 0
+            0x02, 0x66, 0x67,
+        ]),
+        false,
+    );
+
+    while stream.next().unwrap().is_some() {}
+
+    let stats = stream.stats();
+    assert_eq!(stats.bytes(), 18);
+    assert_eq!(stats.packets(), 5);
+    assert_eq!(stats.errors(), 1);
+    assert_eq!(stats.overflows(), 1);
+    assert_eq!(stats.synchronizations(), 1);
+    assert_eq!(stats.kind(PacketKind::Instrumentation), 3);
+    assert_eq!(stats.kind(PacketKind::LocalTimestamp), 0);
+
+    assert_eq!(stats.port(0).packets(), 2);
+    assert_eq!(stats.port(0).bytes(), 3);
+    assert_eq!(stats.port(2).packets(), 1);
+    assert_eq!(stats.port(2).bytes(), 4);
+    assert_eq!(stats.port(1), Default::default());
+    assert_eq!(
+        stats.ports().map(|(port, _)| port).collect::<Vec<_>>(),
+        [0, 2]
+    );
+}
+
+const TPIU: &[u8] = &[
+    // frame
+    0x03, 0x01, 0x40, 0x01, 0x05, 0x42, 0xaa, 0xbb, //
+    0x03, 0x70, 0x02, 0x10, 0x20, 0x03, 0x10, 0xc6, //
+    // full synchronization packet
+    0xff, 0xff, 0xff, 0x7f, //
+    // frame
+    0x22, 0x33, 0x44, 0x70, 0x01, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[test]
+fn tpiu_deframe() {
+    let mut deframer = tpiu::Deframer::new();
+
+    let mut frame = [0; tpiu::FRAME_SIZE];
+    frame.copy_from_slice(&TPIU[..16]);
+    assert_eq!(
+        deframer.deframe(&frame).bytes(),
+        &[
+            (1, 0x01),
+            (1, 0x41),
+            (1, 0x01),
+            // the ID change takes effect after this byte
+            (1, 0x42),
+            (2, 0xaa),
+            (2, 0xbb),
+            (1, 0x70),
+            (1, 0x02),
+            (1, 0x10),
+            (1, 0x21),
+            (1, 0x03),
+            (1, 0x11),
+        ]
+    );
+
+    // the ID carries over to the next frame
+    assert_eq!(deframer.id(), 1);
+    frame.copy_from_slice(&TPIU[20..]);
+    assert_eq!(
+        &deframer.deframe(&frame).bytes()[..5],
+        &[(1, 0x22), (1, 0x33), (1, 0x44), (1, 0x70), (0, 0x00)]
+    );
+    assert_eq!(deframer.id(), tpiu::NULL_ID);
+}
+
+#[test]
+fn tpiu_reader() {
+    let mut data = vec![];
+    let mut reader = tpiu::Reader::new(Cursor::new(TPIU), 2);
+    reader.read_to_end(&mut data).unwrap();
+    assert_eq!(data, &[0xaa, 0xbb]);
+    assert_eq!(reader.discarded(), 24);
+
+    let mut stream = Stream::new(tpiu::Reader::new(Cursor::new(TPIU), tpiu::ITM_ID), false);
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.payload(), b"A"),
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.payload(), b"B"),
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Overflow => {}
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.payload(), &[0x10, 0x21]),
+        _ => panic!(),
+    }
+
+    // this packet spans two frames
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.payload(), &[0x11, 0x22, 0x33, 0x44]),
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Overflow => {}
+        _ => panic!(),
+    }
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
+#[test]
+fn long_synchronization() {
+    // runs of zeros that don't fit in the stream buffer, including one that exactly fills it
+    for &zeros in &[64, 70, 100, 300] {
+        let mut bytes = vec![0; zeros];
+        // end of the synchronization packet followed by an Instrumentation packet
+        bytes.extend_from_slice(&[0b1000_0000, 0x01, 0x10]);
+        let mut stream = Stream::new(Cursor::new(&bytes), false);
+
+        match stream.next().unwrap().unwrap().unwrap() {
+            Packet::Synchronization(s) => assert_eq!(usize::from(s.len()), (zeros + 1).min(255)),
+            _ => panic!(),
+        }
+        assert_eq!(stream.offset(), 0);
+
+        match stream.next().unwrap().unwrap().unwrap() {
+            Packet::Instrumentation(i) => assert_eq!(i.payload(), &[0x10]),
+            _ => panic!(),
+        }
+        assert_eq!(stream.offset(), zeros as u64 + 1);
+
+        // EOF
+        assert!(stream.next().unwrap().is_none());
+
+        // same thing when decoding a slice directly
+        match decode(&bytes) {
+            Some((Ok(Packet::Synchronization(s)), len)) => {
+                assert_eq!(usize::from(s.len()), (zeros + 1).min(255));
+                assert_eq!(len, zeros + 1);
+            }
+            _ => panic!(),
+        }
+    }
+
+    // a long run of zeros truncated by the EOF
+    let mut stream = Stream::new(Cursor::new(vec![0; 100]), false);
+    match stream.next().unwrap().unwrap() {
+        Err(Error::MalformedPacket { header, len }) => {
+            assert_eq!(header, 0);
+            assert_eq!(len, 100);
+        }
+        _ => panic!(),
+    }
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+
+    // `decode` needs the end of the packet
+    assert!(decode(&[0; 300]).is_none());
+}
+
+#[test]
+fn gts2_malformed() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // GTS2; the C bit of the sixth payload byte is set
+            0xb4, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x07,
+        ]),
+        false,
+    );
+
+    match stream.next().unwrap().unwrap() {
+        Err(Error::MalformedPacket { header, len }) => {
+            assert_eq!(header, 0xb4);
+            assert_eq!(len, 6);
+        }
+        _ => panic!(),
+    }
+}
+
+// A reader that returns one chunk of data per `read` call; empty chunks signal a (temporary) EOF
+struct Chunks {
+    chunks: Vec<&'static [u8]>,
+}
+
+impl Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.chunks.is_empty() {
+            return Ok(0);
+        }
+
+        let chunk = self.chunks.remove(0);
+        buf[..chunk.len()].copy_from_slice(chunk);
+        Ok(chunk.len())
+    }
+}
+
+#[test]
+fn split_payload() {
+    let mut stream = Stream::new(
+        Chunks {
+            chunks: vec![
+                // Instrumentation; header and half of the payload
+                &[0x03, 0x10, 0x20],
+                // no data available yet
+                &[],
+                // rest of the payload
+                &[0x30, 0x40],
+            ],
+        },
+        true,
+    );
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.payload(), &[0x10, 0x20, 0x30, 0x40]),
+        _ => panic!(),
+    }
+
+    // without `keep_reading` the temporary EOF truncates the packet
+    let mut stream = Stream::new(
+        Chunks {
+            chunks: vec![&[0x03, 0x10, 0x20], &[], &[0x30, 0x40]],
+        },
+        false,
+    );
+
+    match stream.next().unwrap().unwrap() {
+        Err(Error::MalformedPacket { header, len }) => {
+            assert_eq!(header, 0x03);
+            assert_eq!(len, 3);
+        }
+        _ => panic!(),
+    }
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
+#[test]
+fn synchronization_then_data() {
+    let mut stream = Stream::new(
+        Chunks {
+            chunks: vec![
+                // Synchronization, split across reads
+                &[0x00, 0x00, 0x00],
+                &[0x00, 0x00, 0x80],
+                // Instrumentation whose payload is all zeros
+                &[0x03, 0x00, 0x00],
+                &[0x00, 0x00],
+                // Overflow
+                &[0x70],
+            ],
+        },
+        false,
+    );
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Synchronization(s) => assert_eq!(s.len(), 6),
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => {
+            assert_eq!(i.port(), 0);
+            assert_eq!(i.payload(), &[0x00, 0x00, 0x00, 0x00]);
+        }
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Overflow => {}
+        _ => panic!(),
+    }
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
+#[test]
+fn skip_to_synchronization() {
+    let mut bytes = b"Info : SWO started\r\n\x00".to_vec();
+    // Synchronization
+    bytes.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x80]);
+    // Instrumentation
+    bytes.extend_from_slice(&[0x01, 0x41]);
+    let mut stream = Stream::new(Cursor::new(&bytes), false);
+
+    assert!(stream.skip_to_synchronization().unwrap());
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Synchronization(s) => assert_eq!(s.len(), 7),
+        _ => panic!(),
+    }
+    assert_eq!(stream.offset(), 20);
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.payload(), b"A"),
+        _ => panic!(),
+    }
+    // the skipped bytes are counted too
+    assert_eq!(stream.stats().bytes(), bytes.len() as u64);
+
+    // no synchronization packet
+    let mut stream = Stream::new(
+        Chunks {
+            chunks: vec![
+                &[0x41; 60],
+                &[0x00, 0x00],
+                // EOF
+                &[],
+                // not read; `next` reports the same EOF
+                &[0x01, 0x41],
+            ],
+        },
+        false,
+    );
+    assert!(!stream.skip_to_synchronization().unwrap());
+    assert!(stream.next().unwrap().is_none());
+    assert_eq!(stream.stats().bytes(), 62);
+}
+
+#[test]
+fn reset() {
+    let mut stream = Stream::new(
+        Chunks {
+            chunks: vec![
+                // truncated Instrumentation
+                &[0x03, 0x10],
+                // the link restarts
+                &[],
+                // Instrumentation
+                &[0x01, 0x20],
+            ],
+        },
+        false,
+    );
+
+    assert!(stream.next().unwrap().unwrap().is_err());
+    assert!(stream.next().unwrap().is_none());
+
+    stream.reset();
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.payload(), &[0x20]),
+        _ => panic!(),
+    }
+    assert_eq!(stream.offset(), 2);
+    assert_eq!(stream.stats().bytes(), 4);
+
+    // the discarded bytes are counted too
+    let mut stream = Stream::new(Cursor::new(&[0x01, 0x20, 0x02, 0x30, 0x40]), false);
+    assert!(stream.next().unwrap().unwrap().is_ok());
+    stream.reset();
+    assert!(stream.next().unwrap().is_none());
+    assert_eq!(stream.stats().bytes(), 5);
+    assert_eq!(stream.stats().packets(), 1);
+}
+
+#[test]
+fn decoder() {
+    let mut decoder = Decoder::new();
+    assert!(decoder.next_packet().is_none());
+
+    // Instrumentation; header and half of the payload
+    decoder.feed(&[0x03, 0x10, 0x20]);
+    assert!(decoder.next_packet().is_none());
+
+    // rest of the payload; Overflow; reserved
+    decoder.feed(&[0x30, 0x40, 0x70, 0x04]);
+
+    match decoder.next_packet().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.payload(), &[0x10, 0x20, 0x30, 0x40]),
+        _ => panic!(),
+    }
+
+    match decoder.next_packet().unwrap().unwrap() {
+        Packet::Overflow => {}
+        _ => panic!(),
+    }
+
+    match decoder.next_packet().unwrap() {
+        Err(Error::ReservedHeader { byte }) => assert_eq!(byte, 0x04),
+        _ => panic!(),
+    }
+    assert_eq!(decoder.offset(), 6);
+
+    assert!(decoder.next_packet().is_none());
+
+    // the bytes discarded by `reset` count towards the offset and the statistics
+    decoder.feed(&[0x03, 0x10]);
+    decoder.reset();
+    decoder.feed(&[0x70]);
+    assert!(decoder.next_packet().unwrap().is_ok());
+    assert_eq!(decoder.offset(), 9);
+
+    let stats = decoder.stats();
+    assert_eq!(stats.bytes(), 10);
+    assert_eq!(stats.packets(), 3);
+    assert_eq!(stats.errors(), 1);
+    assert_eq!(stats.overflows(), 2);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test(flavor = "current_thread")]
+async fn async_decoder() {
+    let mut decoder = crate::AsyncDecoder::new(
+        &[
+            // Instrumentation
+            0x01, 0x41, //
+            // Overflow
+            0x70, //
+            // truncated Instrumentation
+            0x03, 0x10,
+        ][..],
+    );
+
+    match decoder.read_packet().await.unwrap().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.payload(), b"A"),
+        _ => panic!(),
+    }
+
+    match decoder.read_packet().await.unwrap().unwrap().unwrap() {
+        Packet::Overflow => {}
+        _ => panic!(),
+    }
+
+    match decoder.read_packet().await.unwrap().unwrap() {
+        Err(Error::MalformedPacket { header, len }) => {
+            assert_eq!(header, 0x03);
+            assert_eq!(len, 2);
+        }
+        _ => panic!(),
+    }
+    assert_eq!(decoder.offset(), 3);
+
+    // EOF
+    assert!(decoder.read_packet().await.unwrap().is_none());
+
+    let stats = decoder.stats();
+    assert_eq!(stats.bytes(), 5);
+    assert_eq!(stats.packets(), 2);
+    assert_eq!(stats.errors(), 1);
+}
+
+#[test]
+fn mixed_stream() {
+    let stream = Stream::new(
+        Cursor::new(&[
+            // Synchronization
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x80, //
+            // GTS2
+            0xb4, 0x81, 0x80, 0x80, 0x00, //
+            // GTS1
+            0x94, 0x7f, //
+            // Instrumentation; port 0
+            0x03, b'I', b'T', b'M', b'\n', //
+            // LTS2
+            0x30, //
+            // Exception Trace; enter SysTick
+            0x0e, 0x0f, 0x10, //
+            // LTS1
+            0xd0, 0x85, 0x01, //
+            // Exception Trace; exit SysTick
+            0x0e, 0x0f, 0x20, //
+            // Event Counter
+            0x05, 0x21, //
+            // Periodic PC Sample
+            0x17, 0x01, 0x02, 0x00, 0x08, //
+            // Periodic PC Sleep
+            0x15, 0x00, //
+            // Data Trace PC Value
+            0x47, 0x04, 0x03, 0x00, 0x08, //
+            // Data Trace Address
+            0x5e, 0x00, 0x20, //
+            // Data Trace Data Value
+            0x9e, 0x34, 0x12, //
+            // Stimulus Port Page
+            0x08, //
+            // Overflow
+            0x70, //
+            // Instrumentation; port 1
+            0x09, 0x2a,
+        ]),
+        false,
+    );
+
+    let output = stream
+        .map(|p| format!("{:?}\n", p.unwrap().unwrap()))
+        .collect::<String>();
+
+    assert_eq!(
+        output,
+        "Synchronization(Synchronization { len: 6 })
+GTS2(GTS2 { bits: 1, b64: false })
+GTS1(GTS1 { bits: 127, clk_ch: false, len: 2, wrap: false })
+Instrumentation(Instrumentation { payload: [73, 84, 77, 10], port: 0 })
+LocalTimestamp(LocalTimestamp { delta: 3, tc: 0, len: 1 })
+ExceptionTrace(ExceptionTrace { function: Enter, number: 15 })
+LocalTimestamp(LocalTimestamp { delta: 133, tc: 1, len: 3 })
+ExceptionTrace(ExceptionTrace { function: Exit, number: 15 })
+EventCounter(EventCounter { payload: 33 })
+PeriodicPcSample(PeriodicPcSample { pc: Some(134218241) })
+PeriodicPcSample(PeriodicPcSample { pc: None })
+DataTracePcValue(DataTracePcValue { cmpn: 0, pc: 134218500 })
+DataTraceAddress(DataTraceAddress { cmpn: 1, address: 8192 })
+DataTraceDataValue(DataTraceDataValue { cmpn: 1, value: [52, 18], wnr: true })
+StimulusPortPage(StimulusPortPage { page: 0 })
+Overflow
+Instrumentation(Instrumentation { payload: [42], port: 1 })
+"
+    );
+}
@@ -0,0 +1,244 @@
+//! TPIU deframing
+//!
+//! When the TPIU (Trace Port Interface Unit) runs in formatted mode, the output of several trace
+//! sources (ITM, ETM, etc.) is multiplexed into a stream of 16-byte frames. This module recovers
+//! the data of each trace source from those frames.
+//!
+//! # References
+//!
+//! - [CoreSight Components Technical Reference Manual (DDI 0314H)][0] - Chapter 9 Trace Port
+//!   Interface Unit, section 9.3.3 Formatter
+//!
+//! [0]: http://infocenter.arm.com/help/topic/com.arm.doc.ddi0314h/DDI0314H_coresight_components_trm.pdf
+
+#[cfg(feature = "std")]
+use std::io::{self, ErrorKind, Read};
+
+/// Trace source ID that indicates that the data is not associated to any trace source
+pub const NULL_ID: u8 = 0x00;
+
+/// Trace source ID conventionally used by the ITM
+pub const ITM_ID: u8 = 0x01;
+
+/// Size in bytes of a TPIU frame
+pub const FRAME_SIZE: usize = 16;
+
+// Full frame synchronization packet (0x7fff_ffff in little endian)
+#[cfg(feature = "std")]
+const FULL_SYNC: [u8; 4] = [0xff, 0xff, 0xff, 0x7f];
+
+/// Deframes TPIU frames
+///
+/// The trace source ID can change in the middle of a frame and carries over to the next frame so
+/// frames must be fed to the deframer in order.
+#[derive(Clone, Copy, Debug)]
+pub struct Deframer {
+    // ID of the trace source the following data bytes belong to
+    id: u8,
+}
+
+impl Default for Deframer {
+    fn default() -> Self {
+        Deframer::new()
+    }
+}
+
+impl Deframer {
+    /// Creates a new deframer
+    ///
+    /// Until the first ID change is seen data bytes are associated to `NULL_ID`
+    pub fn new() -> Deframer {
+        Deframer { id: NULL_ID }
+    }
+
+    /// The ID of the trace source the next data byte belongs to
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Deframes a single TPIU frame
+    pub fn deframe(&mut self, frame: &[u8; FRAME_SIZE]) -> Deframed {
+        let mut deframed = Deframed {
+            bytes: [(0, 0); 15],
+            len: 0,
+        };
+
+        // the last byte of the frame carries the auxiliary bits of the even bytes
+        let aux = frame[15];
+        for n in 0..8 {
+            let byte = frame[2 * n];
+            let aux_bit = (aux >> n) & 1;
+            // the last even byte (byte 14) is not followed by a data byte
+            let next = if n < 7 { Some(frame[2 * n + 1]) } else { None };
+
+            if byte & 1 == 1 {
+                // ID change
+                let id = byte >> 1;
+
+                if let Some(next) = next {
+                    if aux_bit == 0 {
+                        // the new ID takes effect immediately
+                        self.id = id;
+                        deframed.push(self.id, next);
+                    } else {
+                        // the new ID takes effect after the next byte
+                        deframed.push(self.id, next);
+                        self.id = id;
+                    }
+                } else {
+                    self.id = id;
+                }
+            } else {
+                // data byte; its LSB is stored in the auxiliary bits
+                deframed.push(self.id, (byte & !1) | aux_bit);
+
+                if let Some(next) = next {
+                    deframed.push(self.id, next);
+                }
+            }
+        }
+
+        deframed
+    }
+}
+
+/// The data carried by a TPIU frame
+#[derive(Clone, Copy, Debug)]
+pub struct Deframed {
+    bytes: [(u8, u8); 15],
+    len: u8,
+}
+
+impl Deframed {
+    /// The data bytes of the frame, in order, as `(id, byte)` pairs where `id` is the ID of the
+    /// trace source that produced `byte`
+    pub fn bytes(&self) -> &[(u8, u8)] {
+        &self.bytes[..usize::from(self.len)]
+    }
+
+    fn push(&mut self, id: u8, byte: u8) {
+        self.bytes[usize::from(self.len)] = (id, byte);
+        self.len += 1;
+    }
+}
+
+/// Extracts the data of a single trace source from a stream of TPIU frames
+///
+/// This implements `Read` so it can be wrapped in an ITM `Stream`. The inner `Reader` object is
+/// expected to start at a frame boundary; full frame synchronization packets between frames are
+/// skipped.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct Reader<R>
+where
+    R: Read,
+{
+    deframer: Deframer,
+    // number of data bytes that belonged to other trace sources
+    discarded: u64,
+    // the frame being read
+    frame: [u8; FRAME_SIZE],
+    // number of read bytes in `frame`
+    frame_len: usize,
+    // the trace source to extract
+    id: u8,
+    // data bytes of the last deframed frame that belong to `id`
+    data: [u8; 15],
+    // number of bytes in `data`
+    data_len: usize,
+    // start of the bytes in `data` that have not been returned yet
+    data_pos: usize,
+    reader: R,
+}
+
+#[cfg(feature = "std")]
+impl<R> Reader<R>
+where
+    R: Read,
+{
+    /// Creates a reader that extracts the data of trace source `id` from the TPIU frames read
+    /// from the given `Reader` object
+    pub fn new(reader: R, id: u8) -> Reader<R> {
+        Reader {
+            deframer: Deframer::new(),
+            discarded: 0,
+            frame: [0; FRAME_SIZE],
+            frame_len: 0,
+            id,
+            data: [0; 15],
+            data_len: 0,
+            data_pos: 0,
+            reader,
+        }
+    }
+
+    /// Number of data bytes, so far, that belonged to trace sources other than the selected one
+    ///
+    /// These bytes are discarded. This includes the bytes associated to `NULL_ID`.
+    pub fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    // Reads, and deframes, the next frame
+    //
+    // Returns `false` if EOF was reached before a whole frame could be read. The bytes read so far
+    // are kept so reading can be resumed later.
+    fn next_frame(&mut self) -> io::Result<bool> {
+        loop {
+            if self.frame_len == FRAME_SIZE {
+                if self.frame[..4] == FULL_SYNC {
+                    // skip the synchronization packet
+                    for i in 0..FRAME_SIZE - 4 {
+                        self.frame[i] = self.frame[i + 4];
+                    }
+                    self.frame_len -= 4;
+                } else {
+                    break;
+                }
+            }
+
+            match self.reader.read(&mut self.frame[self.frame_len..]) {
+                Ok(0) => return Ok(false),
+                Ok(len) => self.frame_len += len,
+                Err(e) => match e.kind() {
+                    ErrorKind::Interrupted => continue,
+                    _ => return Err(e),
+                },
+            }
+        }
+
+        self.frame_len = 0;
+        self.data_len = 0;
+        self.data_pos = 0;
+        for &(id, byte) in self.deframer.deframe(&self.frame).bytes() {
+            if id == self.id {
+                self.data[self.data_len] = byte;
+                self.data_len += 1;
+            } else {
+                self.discarded += 1;
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R> Read for Reader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.data_pos == self.data_len {
+            if !self.next_frame()? {
+                return Ok(0);
+            }
+        }
+
+        let len = buf.len().min(self.data_len - self.data_pos);
+        buf[..len].copy_from_slice(&self.data[self.data_pos..self.data_pos + len]);
+        self.data_pos += len;
+
+        Ok(len)
+    }
+}